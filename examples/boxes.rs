@@ -0,0 +1,34 @@
+extern crate jpeg2000;
+
+use jpeg2000::boxes::{BoxContent, Boxes};
+
+/// Prints the box tree of the example JP2 file without decoding it.
+fn main() {
+    let data = include_bytes!("./images/rust_logo.jp2");
+    print_boxes(Boxes::new(data), 0);
+}
+
+fn print_boxes(boxes: Boxes, depth: usize) {
+    for jp2_box in boxes {
+        let jp2_box = jp2_box.unwrap();
+        let indent = "  ".repeat(depth);
+        let box_type = String::from_utf8_lossy(&jp2_box.box_type).into_owned();
+        match jp2_box.parse().unwrap() {
+            BoxContent::Superbox(children) => {
+                println!("{}{} at {}", indent, box_type, jp2_box.offset);
+                print_boxes(children, depth + 1);
+            }
+            BoxContent::Codestream(codestream) => println!(
+                "{}{} at {}: {} bytes",
+                indent,
+                box_type,
+                jp2_box.offset,
+                codestream.len()
+            ),
+            content => println!(
+                "{}{} at {}: {:?}",
+                indent, box_type, jp2_box.offset, content
+            ),
+        }
+    }
+}
@@ -53,11 +53,12 @@ fn main() {
             codec,
             DecodeConfig {
                 default_colorspace: Some(ColorSpace::SRGB),
-                discard_level: 0,
+                ..DecodeConfig::default()
             },
-            Some(logger.clone()),
+            Some(logger.clone().into()),
         ).unwrap();
 
+        let img = jpeg2000::DynamicImage::from(img);
         img.save(format!("output/{}.png", basename)).unwrap();
     }
 }
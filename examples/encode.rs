@@ -0,0 +1,45 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{EncodeConfig, Plane, Planes};
+
+/// Encodes the example images losslessly and checks that decoding them again gives the same
+/// pixels.
+fn main() {
+    let images = vec![
+        (include_bytes!("./images/rust_logo.jp2").to_vec(), Codec::JP2),
+        (include_bytes!("./images/opensim_texture.jp2").to_vec(), Codec::J2K),
+    ];
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+
+    for (data, codec) in images {
+        let original = jpeg2000::decode::from_memory(&data, codec, config.clone(), None).unwrap();
+
+        let raw = original.as_raw();
+        let planes: Vec<Vec<u8>> = (0..4)
+            .map(|c| raw.iter().skip(c).step_by(4).cloned().collect())
+            .collect();
+        let planes = Planes {
+            width: original.width(),
+            height: original.height(),
+            color_space: Some(ColorSpace::SRGB),
+            planes: planes.iter().map(|plane| Plane::from(&plane[..])).collect(),
+        };
+
+        for codec in &[Codec::J2K, Codec::JP2] {
+            let encoded =
+                jpeg2000::encode::to_memory(&planes, codec.clone(), EncodeConfig::default(), None)
+                    .unwrap();
+            let decoded =
+                jpeg2000::decode::from_memory(&encoded, codec.clone(), config.clone(), None)
+                    .unwrap();
+            assert!(decoded == original, "{:?} round trip changed the pixels", codec);
+
+            println!("{:?}: {} bytes, round trip is lossless", codec, encoded.len());
+        }
+    }
+}
+
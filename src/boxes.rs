@@ -0,0 +1,549 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use decode::{Palette, PaletteColumn};
+use encode::GridResolution;
+use error::BoxError;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// The four characters identifying the type of a box.
+pub type BoxType = [u8; 4];
+
+pub const SIGNATURE: BoxType = *b"jP  ";
+pub const FILE_TYPE: BoxType = *b"ftyp";
+pub const HEADER: BoxType = *b"jp2h";
+pub const IMAGE_HEADER: BoxType = *b"ihdr";
+pub const BITS_PER_COMPONENT: BoxType = *b"bpcc";
+pub const COLOR: BoxType = *b"colr";
+pub const PALETTE: BoxType = *b"pclr";
+pub const COMPONENT_MAPPING: BoxType = *b"cmap";
+pub const CHANNEL_DEFINITION: BoxType = *b"cdef";
+pub const RESOLUTION: BoxType = *b"res ";
+pub const CAPTURE_RESOLUTION: BoxType = *b"resc";
+pub const DISPLAY_RESOLUTION: BoxType = *b"resd";
+pub const XML: BoxType = *b"xml ";
+pub const UUID: BoxType = *b"uuid";
+pub const UUID_INFO: BoxType = *b"uinf";
+pub const UUID_LIST: BoxType = *b"ulst";
+pub const URL: BoxType = *b"url ";
+pub const ASSOCIATION: BoxType = *b"asoc";
+pub const CODESTREAM: BoxType = *b"jp2c";
+
+/// The payload of the signature box.
+const SIGNATURE_PAYLOAD: [u8; 4] = [0x0d, 0x0a, 0x87, 0x0a];
+
+/// Iterates over the boxes following each other in a file or in the payload of a superbox.
+///
+/// Iteration ends after the first error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Boxes<'a> {
+    data: &'a [u8],
+    position: usize,
+    /// The offset of `data` in the file, for the offsets of the boxes.
+    base: usize,
+    failed: bool,
+}
+
+/// A box with its payload borrowed from the data it was read from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Jp2Box<'a> {
+    pub box_type: BoxType,
+    /// The offset of the box header in the file.
+    pub offset: usize,
+    /// 8 bytes, or 16 with an extended length.
+    pub header_len: usize,
+    pub payload: &'a [u8],
+}
+
+/// The payload of a box parsed according to its type.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BoxContent<'a> {
+    Signature,
+    FileType(FileType),
+    /// The boxes of a `jp2h`, `res `, `uinf` or `asoc` superbox.
+    Superbox(Boxes<'a>),
+    ImageHeader(ImageHeader),
+    BitsPerComponent(Vec<BitDepth>),
+    Color(ColorBox<'a>),
+    Palette(Palette),
+    ComponentMapping(Vec<ComponentMapping>),
+    ChannelDefinition(Vec<ChannelDefinition>),
+    CaptureResolution(GridResolution),
+    DisplayResolution(GridResolution),
+    /// XML data, which isn't checked to be valid UTF-8.
+    Xml(&'a [u8]),
+    Uuid {
+        uuid: [u8; 16],
+        data: &'a [u8],
+    },
+    UuidList(Vec<[u8; 16]>),
+    Url {
+        version: u8,
+        flags: u32,
+        /// The location, without the terminating null byte.
+        location: &'a [u8],
+    },
+    Codestream(&'a [u8]),
+    /// A box of another type, with its payload.
+    Unknown(&'a [u8]),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileType {
+    pub brand: BoxType,
+    pub minor_version: u32,
+    pub compatibility: Vec<BoxType>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub height: u32,
+    pub width: u32,
+    pub components: u16,
+    /// The bit depth of all components, `None` if they differ and are given by a `bpcc` box.
+    pub bit_depth: Option<BitDepth>,
+    /// Always 7 in JP2 files.
+    pub compression: u8,
+    /// Whether the colour space of the image may be wrong.
+    pub unknown_color_space: bool,
+    /// Whether the file contains intellectual property rights information.
+    pub intellectual_property: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BitDepth {
+    pub precision: u8,
+    pub signed: bool,
+}
+
+impl BitDepth {
+    fn from_byte(byte: u8) -> Self {
+        BitDepth {
+            precision: (byte & 0x7f) + 1,
+            signed: byte & 0x80 != 0,
+        }
+    }
+}
+
+/// A colour specification, either enumerated or given by an ICC profile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorBox<'a> {
+    /// 1 for an enumerated colour space, 2 for a restricted and 3 for any ICC profile.
+    pub method: u8,
+    pub precedence: i8,
+    pub approximation: u8,
+    /// The enumerated colour space, e.g. 16 for sRGB and 17 for greyscale.
+    pub enumerated: Option<u32>,
+    pub icc_profile: Option<&'a [u8]>,
+}
+
+/// How a channel of the image is made from a component, directly or through the palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentMapping {
+    pub component: u16,
+    /// The palette column giving the channel, `None` for the component itself.
+    pub palette_column: Option<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChannelDefinition {
+    pub channel: u16,
+    /// 0 for colour, 1 for opacity and 2 for premultiplied opacity.
+    pub channel_type: u16,
+    /// The colour the channel belongs to starting at 1, or 0 for the whole image.
+    pub association: u16,
+}
+
+impl<'a> Boxes<'a> {
+    /// Iterate over the top level boxes of a JP2 file.
+    pub fn new(data: &'a [u8]) -> Self {
+        Boxes {
+            data,
+            position: 0,
+            base: 0,
+            failed: false,
+        }
+    }
+
+    /// The first box of the given type among the remaining boxes.
+    pub fn find(self, box_type: BoxType) -> Result<Option<Jp2Box<'a>>, BoxError> {
+        for jp2_box in self {
+            let jp2_box = jp2_box?;
+            if jp2_box.box_type == box_type {
+                return Ok(Some(jp2_box));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Result<Jp2Box<'a>, BoxError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position == self.data.len() {
+            return None;
+        }
+        let result = read_box(&self.data[self.position..], self.base + self.position);
+        match result {
+            Ok(jp2_box) => self.position += jp2_box.header_len + jp2_box.payload.len(),
+            Err(_) => self.failed = true,
+        }
+        Some(result)
+    }
+}
+
+impl<'a> Jp2Box<'a> {
+    /// Whether the box only contains other boxes.
+    pub fn is_superbox(&self) -> bool {
+        [HEADER, RESOLUTION, UUID_INFO, ASSOCIATION].contains(&self.box_type)
+    }
+
+    /// The boxes inside the payload.
+    pub fn children(&self) -> Boxes<'a> {
+        Boxes {
+            data: self.payload,
+            position: 0,
+            base: self.offset + self.header_len,
+            failed: false,
+        }
+    }
+
+    /// Parse the payload according to the type of the box.
+    pub fn parse(&self) -> Result<BoxContent<'a>, BoxError> {
+        let payload = self.payload;
+        let content = match self.box_type {
+            SIGNATURE => {
+                if payload != SIGNATURE_PAYLOAD {
+                    return Err(self.invalid("wrong signature"));
+                }
+                BoxContent::Signature
+            }
+            FILE_TYPE => {
+                if payload.len() < 8 || !payload.len().is_multiple_of(4) {
+                    return Err(self.invalid("wrong length"));
+                }
+                BoxContent::FileType(FileType {
+                    brand: box_type(payload),
+                    minor_version: read_u32(&payload[4..]),
+                    compatibility: payload[8..].chunks(4).map(box_type).collect(),
+                })
+            }
+            _ if self.is_superbox() => BoxContent::Superbox(self.children()),
+            IMAGE_HEADER => {
+                if payload.len() != 14 {
+                    return Err(self.invalid("wrong length"));
+                }
+                BoxContent::ImageHeader(ImageHeader {
+                    height: read_u32(payload),
+                    width: read_u32(&payload[4..]),
+                    components: read_u16(&payload[8..]),
+                    bit_depth: match payload[10] {
+                        255 => None,
+                        byte => Some(BitDepth::from_byte(byte)),
+                    },
+                    compression: payload[11],
+                    unknown_color_space: payload[12] != 0,
+                    intellectual_property: payload[13] != 0,
+                })
+            }
+            BITS_PER_COMPONENT => {
+                let depths = payload.iter().cloned().map(BitDepth::from_byte);
+                BoxContent::BitsPerComponent(depths.collect())
+            }
+            COLOR => {
+                if payload.len() < 3 {
+                    return Err(self.invalid("too short"));
+                }
+                let (mut enumerated, mut icc_profile) = (None, None);
+                match payload[0] {
+                    1 => {
+                        if payload.len() < 7 {
+                            return Err(self.invalid("too short"));
+                        }
+                        enumerated = Some(read_u32(&payload[3..]));
+                    }
+                    2 | 3 => icc_profile = Some(&payload[3..]),
+                    _ => {}
+                }
+                BoxContent::Color(ColorBox {
+                    method: payload[0],
+                    precedence: payload[1] as i8,
+                    approximation: payload[2],
+                    enumerated,
+                    icc_profile,
+                })
+            }
+            PALETTE => BoxContent::Palette(parse_palette(payload).map_err(|e| self.invalid(e))?),
+            COMPONENT_MAPPING => {
+                if !payload.len().is_multiple_of(4) {
+                    return Err(self.invalid("wrong length"));
+                }
+                // Each channel is described by CMP (u16), MTYP (u8) and PCOL (u8).
+                let channels = payload.chunks(4).map(|channel| ComponentMapping {
+                    component: read_u16(channel),
+                    palette_column: if channel[2] == 1 { Some(channel[3]) } else { None },
+                });
+                BoxContent::ComponentMapping(channels.collect())
+            }
+            CHANNEL_DEFINITION => {
+                if payload.len() < 2 || payload.len() != 2 + 6 * read_u16(payload) as usize {
+                    return Err(self.invalid("wrong length"));
+                }
+                let channels = payload[2..].chunks(6).map(|channel| ChannelDefinition {
+                    channel: read_u16(channel),
+                    channel_type: read_u16(&channel[2..]),
+                    association: read_u16(&channel[4..]),
+                });
+                BoxContent::ChannelDefinition(channels.collect())
+            }
+            CAPTURE_RESOLUTION => BoxContent::CaptureResolution(self.resolution()?),
+            DISPLAY_RESOLUTION => BoxContent::DisplayResolution(self.resolution()?),
+            XML => BoxContent::Xml(payload),
+            UUID => {
+                if payload.len() < 16 {
+                    return Err(self.invalid("too short"));
+                }
+                BoxContent::Uuid {
+                    uuid: uuid(payload),
+                    data: &payload[16..],
+                }
+            }
+            UUID_LIST => {
+                if payload.len() < 2 || payload.len() != 2 + 16 * read_u16(payload) as usize {
+                    return Err(self.invalid("wrong length"));
+                }
+                BoxContent::UuidList(payload[2..].chunks(16).map(uuid).collect())
+            }
+            URL => {
+                if payload.len() < 4 {
+                    return Err(self.invalid("too short"));
+                }
+                let location = &payload[4..];
+                let end = location.iter().position(|b| *b == 0).unwrap_or(location.len());
+                BoxContent::Url {
+                    version: payload[0],
+                    flags: read_u32(payload) & 0x00ff_ffff,
+                    location: &location[..end],
+                }
+            }
+            CODESTREAM => BoxContent::Codestream(payload),
+            _ => BoxContent::Unknown(payload),
+        };
+        Ok(content)
+    }
+
+    /// The vertical and then horizontal resolution, each as numerator and denominator,
+    /// followed by their decimal exponents.
+    fn resolution(&self) -> Result<GridResolution, BoxError> {
+        let payload = self.payload;
+        if payload.len() != 10 {
+            return Err(self.invalid("wrong length"));
+        }
+        let value = |fraction: &[u8], exponent: u8| {
+            let denominator = read_u16(&fraction[2..]);
+            if denominator == 0 {
+                return Err(self.invalid("zero denominator"));
+            }
+            let fraction = f64::from(read_u16(fraction)) / f64::from(denominator);
+            Ok(fraction * 10f64.powi(i32::from(exponent as i8)))
+        };
+        Ok(GridResolution {
+            vertical: value(payload, payload[8])?,
+            horizontal: value(&payload[4..], payload[9])?,
+        })
+    }
+
+    fn invalid(&self, reason: &'static str) -> BoxError {
+        BoxError::InvalidPayload {
+            offset: self.offset,
+            box_type: self.box_type,
+            reason,
+        }
+    }
+}
+
+/// Read the box at the start of `data`.
+fn read_box(data: &[u8], offset: usize) -> Result<Jp2Box<'_>, BoxError> {
+    if data.len() < 8 {
+        return Err(BoxError::TruncatedHeader { offset });
+    }
+    let box_type = box_type(&data[4..]);
+    let (header_len, box_len) = match read_u32(data) {
+        // The last box may extend to the end of the data.
+        0 => (8, data.len() as u64),
+        1 => {
+            if data.len() < 16 {
+                return Err(BoxError::TruncatedHeader { offset });
+            }
+            (16, read_u64(&data[8..]))
+        }
+        len => (8, u64::from(len)),
+    };
+    if box_len < header_len as u64 || box_len > data.len() as u64 {
+        return Err(BoxError::InvalidLength { offset, box_type });
+    }
+    Ok(Jp2Box {
+        box_type,
+        offset,
+        header_len,
+        payload: &data[header_len..box_len as usize],
+    })
+}
+
+/// The header of a box read from a reader, whose payload hasn't been read.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct BoxHeader {
+    /// The position of the box header.
+    pub(crate) start: u64,
+    /// The payload length, `None` for a box extending to the end of the file.
+    pub(crate) payload_len: Option<u64>,
+}
+
+/// Move the reader to the payload of the first box of the given type, starting at the current
+/// position.
+///
+/// Returns `None` if there's no such box, or a box before it extends to the end of the file or
+/// is shorter than its header. A box too long to be skipped is an `InvalidData` error holding a
+/// `BoxError::InvalidLength`.
+pub(crate) fn seek_box<R: Read + Seek>(
+    reader: &mut R,
+    box_type: BoxType,
+) -> io::Result<Option<BoxHeader>> {
+    let mut header = [0u8; 8];
+    loop {
+        let start = reader.stream_position()?;
+        if reader.read(&mut header[..1])? == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut header[1..])?;
+        let (header_len, box_len) = match read_u32(&header) {
+            0 => (8, None),
+            1 => {
+                let mut xl_box = [0u8; 8];
+                reader.read_exact(&mut xl_box)?;
+                (16, Some(read_u64(&xl_box)))
+            }
+            len => (8, Some(u64::from(len))),
+        };
+        let payload_len = match box_len {
+            Some(box_len) if box_len < header_len => return Ok(None),
+            Some(box_len) => Some(box_len - header_len),
+            None => None,
+        };
+        if header[4..8] == box_type {
+            return Ok(Some(BoxHeader { start, payload_len }));
+        }
+        let payload_len = match payload_len {
+            Some(payload_len) => payload_len,
+            None => return Ok(None),
+        };
+        // A length beyond the range of seeks would move the reader backwards, and find the same
+        // box again forever.
+        let invalid_length = || {
+            let error = BoxError::InvalidLength {
+                offset: start as usize,
+                box_type: [header[4], header[5], header[6], header[7]],
+            };
+            io::Error::new(io::ErrorKind::InvalidData, error)
+        };
+        if payload_len > i64::MAX as u64 {
+            return Err(invalid_length());
+        }
+        if reader.seek(SeekFrom::Current(payload_len as i64))? <= start {
+            return Err(invalid_length());
+        }
+    }
+}
+
+/// Parse a `pclr` payload, returning the reason it is invalid otherwise.
+fn parse_palette(pclr: &[u8]) -> Result<Palette, &'static str> {
+    if pclr.len() < 3 {
+        return Err("truncated");
+    }
+    let num_entries = read_u16(pclr) as usize;
+    let num_columns = pclr[2] as usize;
+    if num_entries == 0 || num_entries > 1024 || num_columns == 0 {
+        return Err("invalid dimensions");
+    }
+    if pclr.len() < 3 + num_columns {
+        return Err("truncated");
+    }
+
+    let columns: Vec<PaletteColumn> = pclr[3..3 + num_columns]
+        .iter()
+        .map(|b| PaletteColumn {
+            precision: (b & 0x7f) + 1,
+            signed: b & 0x80 != 0,
+        })
+        .collect();
+    if columns.iter().any(|c| c.precision > 32) {
+        return Err("bit depth too large");
+    }
+
+    let mut data = &pclr[3 + num_columns..];
+    let mut entries = Vec::with_capacity(num_entries);
+    for _ in 0..num_entries {
+        let mut entry = Vec::with_capacity(num_columns);
+        for column in &columns {
+            let size = (column.precision as usize).div_ceil(8);
+            if data.len() < size {
+                return Err("truncated");
+            }
+            let raw = data[..size]
+                .iter()
+                .fold(0u32, |acc, b| (acc << 8) | u32::from(*b));
+            data = &data[size..];
+
+            let value = if column.signed && column.precision < 32 {
+                // Sign extend the value from its bit depth.
+                let shift = 32 - u32::from(column.precision);
+                ((raw << shift) as i32) >> shift
+            } else {
+                raw as i32
+            };
+            entry.push(value);
+        }
+        entries.push(entry);
+    }
+
+    Ok(Palette {
+        columns,
+        entries,
+    })
+}
+
+fn box_type(data: &[u8]) -> BoxType {
+    [data[0], data[1], data[2], data[3]]
+}
+
+fn uuid(data: &[u8]) -> [u8; 16] {
+    let mut uuid = [0; 16];
+    uuid.copy_from_slice(&data[..16]);
+    uuid
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (u32::from(read_u16(data)) << 16) | u32::from(read_u16(&data[2..]))
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    (u64::from(read_u32(data)) << 32) | u64::from(read_u32(&data[4..]))
+}
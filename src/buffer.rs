@@ -0,0 +1,93 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#[cfg(feature = "image")]
+use image::{DynamicImage, RgbaImage};
+
+/// An image with 8-bit RGBA pixels, stored row by row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RgbaBuffer {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl RgbaBuffer {
+    /// Create a fully transparent black image.
+    pub fn new(width: u32, height: u32) -> Self {
+        RgbaBuffer {
+            width,
+            height,
+            data: vec![0; width as usize * height as usize * 4],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The RGBA value of the pixel at the given position.
+    ///
+    /// Panics if the position is outside of the image.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = self.index(x, y);
+        [
+            self.data[i],
+            self.data[i + 1],
+            self.data[i + 2],
+            self.data[i + 3],
+        ]
+    }
+
+    pub(crate) fn put_pixel(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        let i = self.index(x, y);
+        self.data[i..i + 4].copy_from_slice(&pixel);
+    }
+
+    /// The raw pixel data, four bytes per pixel.
+    pub fn as_raw(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Take the raw pixel data, four bytes per pixel.
+    pub fn into_raw(self) -> Vec<u8> {
+        self.data
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height, "pixel out of bounds");
+        (y as usize * self.width as usize + x as usize) * 4
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<RgbaBuffer> for RgbaImage {
+    fn from(buffer: RgbaBuffer) -> Self {
+        RgbaImage::from_raw(buffer.width, buffer.height, buffer.data)
+            .expect("buffer size matches the dimensions")
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<RgbaBuffer> for DynamicImage {
+    fn from(buffer: RgbaBuffer) -> Self {
+        DynamicImage::ImageRgba8(buffer.into())
+    }
+}
@@ -0,0 +1,571 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use boxes;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const SOC: u16 = 0xff4f;
+const SIZ: u16 = 0xff51;
+const COD: u16 = 0xff52;
+const COC: u16 = 0xff53;
+const TLM: u16 = 0xff55;
+const PLT: u16 = 0xff58;
+const SOP: u16 = 0xff91;
+const SOT: u16 = 0xff90;
+const SOD: u16 = 0xff93;
+const EOC: u16 = 0xffd9;
+const COM: u16 = 0xff64;
+
+/// Information read from the marker segments of a codestream, without decoding it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodestreamInfo {
+    /// The comments of the main header and of all tile-part headers, in codestream order.
+    pub comments: Vec<Comment>,
+    /// The coding modes used by any tile or component.
+    pub modes: CodingModes,
+    /// The index markers found in the main header or any tile-part header.
+    pub index_markers: IndexMarkers,
+    /// Whether the codestream ended before its end of codestream marker, either within a marker
+    /// segment or before the end of a tile-part given by its length. A last tile-part with a
+    /// length of 0 runs to the end of the codestream, so its data can't be checked.
+    pub truncated: bool,
+}
+
+/// Marker segments listing the lengths of tile-parts and packets, which let decoders jump to a
+/// region of the image without reading everything before it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndexMarkers {
+    /// TLM marker segments in the main header, with the length of every tile-part.
+    pub tile_part_lengths: bool,
+    /// PLT marker segments in the tile-part headers, with the length of every packet.
+    pub packet_lengths: bool,
+}
+
+/// Error resilience markers and code-block coding modes (the COD and COC coding styles).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CodingModes {
+    /// Start of packet markers before each packet, so a decoder can resynchronise.
+    pub sop_markers: bool,
+    /// End of packet header markers after each packet header.
+    pub eph_markers: bool,
+    /// Selective arithmetic coding bypass (BYPASS), raw coding of the lower bit-planes.
+    pub bypass: bool,
+    /// Reset the context probabilities after each coding pass (RESET).
+    pub reset: bool,
+    /// Terminate the arithmetic coder after each coding pass (RESTART or TERMALL).
+    pub termall: bool,
+    /// Vertically causal context formation (VSC).
+    pub vertically_causal: bool,
+    /// Predictable termination (PTERM), which lets decoders detect corrupted passes.
+    pub predictable_termination: bool,
+    /// Segmentation symbols at the end of each cleanup pass (SEGSYM).
+    pub segmentation_symbols: bool,
+}
+
+impl CodingModes {
+    /// The code-block style of COD and COC marker segments.
+    pub(crate) fn code_block_style(&self) -> u8 {
+        self.bypass as u8
+            | (self.reset as u8) << 1
+            | (self.termall as u8) << 2
+            | (self.vertically_causal as u8) << 3
+            | (self.predictable_termination as u8) << 4
+            | (self.segmentation_symbols as u8) << 5
+    }
+
+    fn add_code_block_style(&mut self, style: u8) {
+        self.bypass |= style & 0x01 != 0;
+        self.reset |= style & 0x02 != 0;
+        self.termall |= style & 0x04 != 0;
+        self.vertically_causal |= style & 0x08 != 0;
+        self.predictable_termination |= style & 0x10 != 0;
+        self.segmentation_symbols |= style & 0x20 != 0;
+    }
+}
+
+/// The content of a COM marker segment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comment {
+    /// The tile whose tile-part header holds the comment, `None` for the main header.
+    pub tile: Option<u16>,
+    pub data: CommentData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommentData {
+    /// A Latin-1 text comment.
+    Text(String),
+    /// A binary comment, or one of an unknown registration.
+    Binary(Vec<u8>),
+}
+
+/// Read the marker segments of a codestream starting at the current position of the reader.
+///
+/// The image data is skipped using the tile-part lengths. A codestream which ends early is
+/// scanned as far as it goes and marked as truncated.
+pub fn scan<R: Read + Seek>(reader: &mut R) -> io::Result<CodestreamInfo> {
+    let mut info = CodestreamInfo::default();
+    if read_marker(reader)? != SOC {
+        return Err(invalid_data("the codestream doesn't start with SOC"));
+    }
+
+    let mut tile = None;
+    let mut num_components = 0;
+    let mut tile_part_start = 0;
+    let mut tile_part_len = 0;
+    loop {
+        let marker = match read_marker(reader) {
+            Ok(marker) => marker,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                info.truncated = true;
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        match marker {
+            EOC => break,
+            SOD => {
+                // Psot of 0 means the tile-part extends to the end of the codestream.
+                if tile_part_len == 0 {
+                    break;
+                }
+                // A tile-part ending before its data would make the scan read it again.
+                let end = tile_part_start + u64::from(tile_part_len);
+                if end < reader.stream_position()? {
+                    return Err(invalid_data("tile-part length shorter than its header"));
+                }
+                reader.seek(SeekFrom::Start(end))?;
+                continue;
+            }
+            // Markers without a segment.
+            0xff30..=0xff3f => continue,
+            _ => {}
+        }
+
+        let segment = match read_segment(reader) {
+            Ok(segment) => segment,
+            Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                info.truncated = true;
+                break;
+            }
+            Err(err) => return Err(err),
+        };
+        match marker {
+            SOT => {
+                if segment.len() < 8 {
+                    return Err(invalid_data("SOT marker segment too short"));
+                }
+                // The tile-part length counts from the start of the SOT marker.
+                tile_part_start = reader.stream_position()? - segment.len() as u64 - 4;
+                tile = Some(read_u16(&segment));
+                tile_part_len = read_u32(&segment[2..]);
+            }
+            COM => info.comments.push(parse_comment(&segment, tile)?),
+            TLM => info.index_markers.tile_part_lengths = true,
+            PLT => info.index_markers.packet_lengths = true,
+            SIZ => {
+                if segment.len() < 36 {
+                    return Err(invalid_data("SIZ marker segment too short"));
+                }
+                num_components = read_u16(&segment[34..]);
+            }
+            COD => {
+                if segment.len() < 9 {
+                    return Err(invalid_data("COD marker segment too short"));
+                }
+                info.modes.sop_markers |= segment[0] & 0x02 != 0;
+                info.modes.eph_markers |= segment[0] & 0x04 != 0;
+                info.modes.add_code_block_style(segment[8]);
+            }
+            COC => {
+                // The component index takes two bytes with more than 256 components.
+                let style = if num_components > 256 { 6 } else { 5 };
+                if segment.len() <= style {
+                    return Err(invalid_data("COC marker segment too short"));
+                }
+                info.modes.add_code_block_style(segment[style]);
+            }
+            _ => {}
+        }
+    }
+    Ok(info)
+}
+
+/// Read the marker segments of a codestream in memory, see `scan`.
+pub fn scan_memory(buf: &[u8]) -> io::Result<CodestreamInfo> {
+    scan(&mut io::Cursor::new(buf))
+}
+
+/// Move the reader of a JP2 file to the payload of its contiguous codestream box.
+///
+/// Returns the position of the box header, or `None` if the file has no codestream box.
+pub(crate) fn seek_jp2c<R: Read + Seek>(reader: &mut R) -> io::Result<Option<u64>> {
+    let header = boxes::seek_box(reader, boxes::CODESTREAM)?;
+    Ok(header.map(|header| header.start))
+}
+
+fn parse_comment(segment: &[u8], tile: Option<u16>) -> io::Result<Comment> {
+    if segment.len() < 2 {
+        return Err(invalid_data("COM marker segment too short"));
+    }
+    let data = match read_u16(segment) {
+        1 => CommentData::Text(segment[2..].iter().map(|b| char::from(*b)).collect()),
+        _ => CommentData::Binary(segment[2..].to_vec()),
+    };
+    Ok(Comment { tile, data })
+}
+
+/// Edit the codestream of a JP2 file, fixing the length of its box.
+pub(crate) fn edit_jp2_codestream<F>(file: &mut Vec<u8>, edit: F) -> io::Result<()>
+where
+    F: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+{
+    let mut reader = io::Cursor::new(&file[..]);
+    let header_start = match seek_jp2c(&mut reader)? {
+        Some(header_start) => header_start as usize,
+        None => return Err(invalid_data("the file has no codestream box")),
+    };
+    let start = reader.position() as usize;
+    let mut codestream = file.split_off(start);
+    let old_len = codestream.len() as u64;
+    edit(&mut codestream)?;
+    let new_len = codestream.len() as u64;
+
+    // A length of 0 means the box extends to the end of the file and needs no update.
+    match read_u32(&file[header_start..]) {
+        0 => {}
+        1 => {
+            let len = read_u64(&file[header_start + 8..]) + new_len - old_len;
+            file[header_start + 8..header_start + 16].copy_from_slice(&len.to_be_bytes());
+        }
+        len => {
+            let len = u64::from(len) + new_len - old_len;
+            if len > u64::from(u32::MAX) {
+                return Err(invalid_data("the codestream box is too large"));
+            }
+            file[header_start..header_start + 4].copy_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    file.append(&mut codestream);
+    Ok(())
+}
+
+/// Insert text comments into the main header of a codestream after the first COM marker, or
+/// before the first tile-part if there is none.
+pub(crate) fn insert_comments(codestream: &mut Vec<u8>, comments: &[String]) -> io::Result<()> {
+    let mut segments = Vec::new();
+    for comment in comments {
+        let text = match latin1(comment) {
+            Some(text) => text,
+            None => return Err(invalid_data("comment isn't Latin-1 text")),
+        };
+        let len = text.len() + 4;
+        if len > 0xffff {
+            return Err(invalid_data("comment too long"));
+        }
+        segments.extend_from_slice(&COM.to_be_bytes());
+        segments.extend_from_slice(&(len as u16).to_be_bytes());
+        segments.extend_from_slice(&1u16.to_be_bytes());
+        segments.extend_from_slice(&text);
+    }
+
+    let position = comment_position(codestream)?;
+    codestream.splice(position..position, segments);
+    Ok(())
+}
+
+/// Encode text as Latin-1, the registration of text comments, `None` if a character isn't part
+/// of it.
+pub(crate) fn latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| if (c as u32) < 0x100 { Some(c as u8) } else { None })
+        .collect()
+}
+
+/// The offset in the main header after which new comments go.
+fn comment_position(codestream: &[u8]) -> io::Result<usize> {
+    if codestream.len() < 2 || read_u16(codestream) != SOC {
+        return Err(invalid_data("the codestream doesn't start with SOC"));
+    }
+    let mut position = 2;
+    while position + 4 <= codestream.len() {
+        let marker = read_u16(&codestream[position..]);
+        if marker == SOT || marker == EOC {
+            return Ok(position);
+        }
+        let end = position + 2 + read_u16(&codestream[position + 2..]) as usize;
+        if marker == COM {
+            return Ok(end);
+        }
+        position = end;
+    }
+    Err(invalid_data("the main header is truncated"))
+}
+
+/// Add TLM and PLT marker segments to a codestream.
+///
+/// Packets are found by their SOP markers, which the codestream needs for packet lengths. The
+/// SOP markers are removed afterwards unless `keep_sop` is set.
+pub(crate) fn insert_index_markers(
+    codestream: &mut Vec<u8>,
+    markers: IndexMarkers,
+    keep_sop: bool,
+) -> io::Result<()> {
+    if markers.packet_lengths {
+        insert_packet_lengths(codestream, keep_sop)?;
+    }
+    if markers.tile_part_lengths {
+        insert_tile_part_lengths(codestream)?;
+    }
+    Ok(())
+}
+
+/// A tile-part of a codestream, as offsets into it.
+struct TilePart {
+    start: usize,
+    /// The offset of the SOD marker.
+    sod: usize,
+    end: usize,
+    tile: u16,
+}
+
+/// Find the tile-parts following the main header, which ends at `position`.
+fn tile_parts(codestream: &[u8], mut position: usize) -> io::Result<Vec<TilePart>> {
+    let mut parts = Vec::new();
+    while position + 2 <= codestream.len() {
+        match read_u16(&codestream[position..]) {
+            SOT => {}
+            EOC => break,
+            _ => return Err(invalid_data("expected a tile-part")),
+        }
+        if position + 12 > codestream.len() {
+            return Err(invalid_data("SOT marker segment too short"));
+        }
+        let tile = read_u16(&codestream[position + 4..]);
+        let end = match read_u32(&codestream[position + 6..]) {
+            // The last tile-part may extend to the end of codestream marker.
+            0 => codestream.len() - 2,
+            len => position + len as usize,
+        };
+        if end > codestream.len() {
+            return Err(invalid_data("a tile-part exceeds the codestream"));
+        }
+
+        let mut sod = position + 12;
+        while sod + 4 <= end && read_u16(&codestream[sod..]) != SOD {
+            sod += 2 + read_u16(&codestream[sod + 2..]) as usize;
+        }
+        if sod + 2 > end || read_u16(&codestream[sod..]) != SOD {
+            return Err(invalid_data("a tile-part header isn't terminated by SOD"));
+        }
+        parts.push(TilePart {
+            start: position,
+            sod,
+            end,
+            tile,
+        });
+        position = end;
+    }
+    Ok(parts)
+}
+
+/// The offset of the first tile-part, where the main header ends.
+fn main_header_end(codestream: &[u8]) -> io::Result<usize> {
+    if codestream.len() < 2 || read_u16(codestream) != SOC {
+        return Err(invalid_data("the codestream doesn't start with SOC"));
+    }
+    let mut position = 2;
+    while position + 4 <= codestream.len() {
+        let marker = read_u16(&codestream[position..]);
+        if marker == SOT || marker == EOC {
+            return Ok(position);
+        }
+        position += 2 + read_u16(&codestream[position + 2..]) as usize;
+    }
+    Err(invalid_data("the main header is truncated"))
+}
+
+/// Clear the SOP flag of the COD marker segments in a header, starting with a marker segment.
+fn clear_sop_flag(header: &mut [u8]) {
+    let mut position = 0;
+    while position + 5 <= header.len() {
+        if read_u16(&header[position..]) == COD {
+            header[position + 4] &= !0x02;
+        }
+        position += 2 + read_u16(&header[position + 2..]) as usize;
+    }
+}
+
+/// Write marker segments holding `entries`, splitting them so no segment is too long and
+/// numbering the segments with the index byte after the length.
+fn write_index_segments(
+    output: &mut Vec<u8>,
+    marker: u16,
+    prefix: &[u8],
+    entries: &[Vec<u8>],
+) -> io::Result<()> {
+    let mut index = 0u8;
+    let mut remaining = entries;
+    while !remaining.is_empty() {
+        let mut len = 3 + prefix.len();
+        let count = remaining
+            .iter()
+            .take_while(|entry| {
+                len += entry.len();
+                len <= 0xffff
+            })
+            .count();
+        let (segment, rest) = remaining.split_at(count);
+
+        output.extend_from_slice(&marker.to_be_bytes());
+        let len = 3 + prefix.len() + segment.iter().map(Vec::len).sum::<usize>();
+        output.extend_from_slice(&(len as u16).to_be_bytes());
+        output.push(index);
+        output.extend_from_slice(prefix);
+        for entry in segment {
+            output.extend_from_slice(entry);
+        }
+
+        remaining = rest;
+        if !remaining.is_empty() {
+            index = index
+                .checked_add(1)
+                .ok_or_else(|| invalid_data("too many index marker segments"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Insert PLT marker segments into every tile-part header.
+fn insert_packet_lengths(codestream: &mut Vec<u8>, keep_sop: bool) -> io::Result<()> {
+    let header_end = main_header_end(codestream)?;
+    let parts = tile_parts(codestream, header_end)?;
+    let mut output = codestream[..header_end].to_vec();
+    if !keep_sop {
+        clear_sop_flag(&mut output[2..]);
+    }
+
+    for part in &parts {
+        // SOP markers can't occur inside packets, since the packet data is bit stuffed.
+        let data = &codestream[part.sod + 2..part.end];
+        let mut packets = Vec::new();
+        for i in 0..data.len().saturating_sub(5) {
+            if read_u16(&data[i..]) == SOP && read_u16(&data[i + 2..]) == 4 {
+                packets.push(i);
+            }
+        }
+        if !data.is_empty() && packets.first() != Some(&0) {
+            return Err(invalid_data("packet lengths need SOP markers"));
+        }
+        packets.push(data.len());
+
+        let skip = if keep_sop { 0 } else { 6 };
+        let mut lengths = Vec::new();
+        let mut packet_data = Vec::with_capacity(data.len());
+        for bounds in packets.windows(2) {
+            packet_data.extend_from_slice(&data[bounds[0] + skip..bounds[1]]);
+            lengths.push(packet_length(bounds[1] - bounds[0] - skip));
+        }
+
+        let mut header = codestream[part.start..part.sod].to_vec();
+        if !keep_sop {
+            clear_sop_flag(&mut header[12..]);
+        }
+        write_index_segments(&mut header, PLT, &[], &lengths)?;
+        header.extend_from_slice(&SOD.to_be_bytes());
+
+        let len = header.len() + packet_data.len();
+        if len > u32::MAX as usize {
+            return Err(invalid_data("a tile-part is too large"));
+        }
+        header[6..10].copy_from_slice(&(len as u32).to_be_bytes());
+        output.append(&mut header);
+        output.append(&mut packet_data);
+    }
+
+    let rest = parts.last().map_or(header_end, |part| part.end);
+    output.extend_from_slice(&codestream[rest..]);
+    *codestream = output;
+    Ok(())
+}
+
+/// The variable length encoding of a packet length, seven bits per byte.
+fn packet_length(mut len: usize) -> Vec<u8> {
+    let mut bytes = vec![(len & 0x7f) as u8];
+    len >>= 7;
+    while len > 0 {
+        bytes.insert(0, 0x80 | (len & 0x7f) as u8);
+        len >>= 7;
+    }
+    bytes
+}
+
+/// Insert TLM marker segments at the end of the main header.
+fn insert_tile_part_lengths(codestream: &mut Vec<u8>) -> io::Result<()> {
+    let header_end = main_header_end(codestream)?;
+    let entries: Vec<Vec<u8>> = tile_parts(codestream, header_end)?
+        .iter()
+        .map(|part| {
+            let mut entry = part.tile.to_be_bytes().to_vec();
+            entry.extend_from_slice(&((part.end - part.start) as u32).to_be_bytes());
+            entry
+        })
+        .collect();
+
+    // Stlm: 16-bit tile indices and 32-bit lengths.
+    let mut segments = Vec::new();
+    write_index_segments(&mut segments, TLM, &[0x60], &entries)?;
+    codestream.splice(header_end..header_end, segments);
+    Ok(())
+}
+
+fn read_marker<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+    if marker[0] != 0xff {
+        return Err(invalid_data("expected a marker"));
+    }
+    Ok(read_u16(&marker))
+}
+
+/// Read the content of a marker segment, without its length.
+fn read_segment<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 2];
+    reader.read_exact(&mut len)?;
+    let len = read_u16(&len);
+    if len < 2 {
+        return Err(invalid_data("invalid marker segment length"));
+    }
+    let mut segment = vec![0u8; len as usize - 2];
+    reader.read_exact(&mut segment)?;
+    Ok(segment)
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    (u16::from(data[0]) << 8) | u16::from(data[1])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    (u32::from(read_u16(data)) << 16) | u32::from(read_u16(&data[2..]))
+}
+
+fn read_u64(data: &[u8]) -> u64 {
+    (u64::from(read_u32(data)) << 32) | u64::from(read_u32(&data[4..]))
+}
@@ -0,0 +1,119 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{decode_file, decode_memory, Codec, DecodeConfig, DecodeReport, Scratch};
+use buffer::RgbaBuffer;
+use error::DecodeError;
+use rayon::prelude::*;
+use logging::Logger;
+use std::cell::RefCell;
+use std::path::Path;
+
+thread_local! {
+    /// The state reused by all decodes on a thread of the pool. It keeps the buffer of the
+    /// largest file read on the thread for later batches.
+    static SCRATCH: RefCell<Scratch> = RefCell::new(Scratch::new());
+}
+
+/// Decode all buffers in parallel using the same codec and config.
+///
+/// The results are returned in the same order as the buffers, with one `Result` per item, so a
+/// single broken image doesn't fail the whole batch.
+pub fn from_memory<B>(
+    buffers: &[B],
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Option<Logger>,
+) -> Vec<Result<RgbaBuffer, DecodeError>>
+where
+    B: AsRef<[u8]> + Sync,
+{
+    without_reports(from_memory_with_report(buffers, codec, config, logger))
+}
+
+/// Like `from_memory`, but also returns a report about the decoding of each image.
+pub fn from_memory_with_report<B>(
+    buffers: &[B],
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Option<Logger>,
+) -> Vec<Result<(RgbaBuffer, DecodeReport), DecodeError>>
+where
+    B: AsRef<[u8]> + Sync,
+{
+    let logger = logger.unwrap_or_default();
+
+    buffers
+        .par_iter()
+        .enumerate()
+        .map(|(index, buf)| {
+            let logger = logger.with_batch_index(index);
+            SCRATCH.with(|scratch| {
+                let scratch = &mut scratch.borrow_mut();
+                decode_memory(buf.as_ref(), codec.clone(), config, logger, scratch)
+            })
+        })
+        .collect()
+}
+
+/// Decode all files in parallel using the same codec and config.
+///
+/// Each thread reuses its buffer for the contents of the files it reads.
+pub fn from_files<P>(
+    file_names: &[P],
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Option<Logger>,
+) -> Vec<Result<RgbaBuffer, DecodeError>>
+where
+    P: AsRef<Path> + Sync,
+{
+    without_reports(from_files_with_report(file_names, codec, config, logger))
+}
+
+/// Like `from_files`, but also returns a report about the decoding of each image.
+pub fn from_files_with_report<P>(
+    file_names: &[P],
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Option<Logger>,
+) -> Vec<Result<(RgbaBuffer, DecodeReport), DecodeError>>
+where
+    P: AsRef<Path> + Sync,
+{
+    let logger = logger.unwrap_or_default();
+
+    file_names
+        .par_iter()
+        .enumerate()
+        .map(|(index, file_name)| {
+            let logger = logger.with_batch_index(index);
+            SCRATCH.with(|scratch| {
+                let scratch = &mut scratch.borrow_mut();
+                decode_file(file_name.as_ref(), codec.clone(), config, logger, scratch)
+            })
+        })
+        .collect()
+}
+
+fn without_reports(
+    results: Vec<Result<(RgbaBuffer, DecodeReport), DecodeError>>,
+) -> Vec<Result<RgbaBuffer, DecodeError>> {
+    results
+        .into_iter()
+        .map(|result| result.map(|(image, _)| image))
+        .collect()
+}
@@ -14,7 +14,7 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use image::Rgba;
+use openjpeg2_sys as ffi;
 
 /// This is the type only describing the actual ColorSpaces and doesn't allow for the `Unknown` and
 /// `Unspecified` variant.
@@ -28,6 +28,18 @@ pub enum ColorSpace {
     SYCC,
 }
 
+impl ColorSpace {
+    pub(crate) fn to_i32(&self) -> i32 {
+        match *self {
+            ColorSpace::CMYK => ffi::COLOR_SPACE_OPJ_CLRSPC_CMYK,
+            ColorSpace::EYCC => ffi::COLOR_SPACE_OPJ_CLRSPC_EYCC,
+            ColorSpace::GRAY => ffi::COLOR_SPACE_OPJ_CLRSPC_GRAY,
+            ColorSpace::SRGB => ffi::COLOR_SPACE_OPJ_CLRSPC_SRGB,
+            ColorSpace::SYCC => ffi::COLOR_SPACE_OPJ_CLRSPC_SYCC,
+        }
+    }
+}
+
 /// This is a type used for decoding the color space type as provided by the C API.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ColorSpaceValue {
@@ -58,13 +70,21 @@ pub const MAX_COMPONENTS: usize = 4;
 type ArrComponents = [u8; MAX_COMPONENTS];
 
 impl ColorSpace {
-    pub fn convert_to_rgba(&self, source: ArrComponents) -> Rgba<u8> {
-        let result: [u8; 4] = match *self {
+    pub fn convert_to_rgba(&self, source: ArrComponents) -> [u8; 4] {
+        self.to_rgba(source, u8::MAX)
+    }
+
+    /// Like `convert_to_rgba`, for 16-bit samples.
+    #[cfg(feature = "image")]
+    pub(crate) fn convert_to_rgba16(&self, source: [u16; MAX_COMPONENTS]) -> [u16; 4] {
+        self.to_rgba(source, u16::MAX)
+    }
+
+    fn to_rgba<T: Copy>(&self, source: [T; MAX_COMPONENTS], opaque: T) -> [T; 4] {
+        match *self {
             ColorSpace::SRGB => source,
-            ColorSpace::GRAY => [source[0], source[0], source[0], 255],
+            ColorSpace::GRAY => [source[0], source[0], source[0], opaque],
             _ => unimplemented!(),
-        };
-
-        Rgba { data: result }
+        }
     }
 }
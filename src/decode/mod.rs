@@ -14,26 +14,44 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use buffer::RgbaBuffer;
+use codestream::{self, CodestreamInfo};
 use error::DecodeError;
-use image::{DynamicImage, GenericImage};
 use openjpeg2_sys as ffi;
-use slog::{self, Logger};
-use std::ffi::CString;
-use std::os::raw::c_void;
-use std::ptr::null_mut;
+use logging::{Logger, OpjLogger};
+use raw::{Decoder, Image, Stream};
+use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::mem;
+use std::path::Path;
+use std::time::Instant;
 
 mod color_convert;
 use self::color_convert::ColorSpaceValue;
 pub use self::color_convert::ColorSpace;
 
-mod support;
+mod palette;
+pub use self::palette::{indexed_from_file, indexed_from_memory, IndexedImage, Palette,
+                        PaletteColumn};
 
+mod report;
+pub use self::report::DecodeReport;
+
+pub(crate) mod support;
+
+/// Parallel decoding of many images at once (requires the `rayon` feature).
+#[cfg(feature = "rayon")]
+pub mod batch;
+
+#[derive(Clone)]
 pub struct DecodeConfig {
     /// Default color space to be used in the case of unspecified values.
     pub default_colorspace: Option<ColorSpace>,
     /// The image resolution is effectively divided by 2 to the power of
     /// the number of discarded levels.
     pub discard_level: u32,
+    /// Maximum number of quality layers to decode, 0 decodes all layers.
+    pub quality_layers: u32,
 }
 
 impl Default for DecodeConfig {
@@ -41,6 +59,7 @@ impl Default for DecodeConfig {
         DecodeConfig {
             default_colorspace: None,
             discard_level: 0,
+            quality_layers: 0,
         }
     }
 }
@@ -59,8 +78,26 @@ pub enum Codec {
     JPX,
 }
 
+/// The JP2 signature box, which also starts JPX files.
+pub(crate) const JP2_SIGNATURE: [u8; 12] = [
+    0x00, 0x00, 0x00, 0x0c, b'j', b'P', b' ', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+];
+/// The SOC marker followed by the SIZ marker.
+pub(crate) const J2K_SIGNATURE: [u8; 4] = [0xff, 0x4f, 0xff, 0x51];
+
 impl Codec {
-    fn to_i32(&self) -> i32 {
+    /// Guess the codec from the first bytes of a file.
+    pub fn detect(buf: &[u8]) -> Option<Codec> {
+        if buf.starts_with(&JP2_SIGNATURE) {
+            Some(Codec::JP2)
+        } else if buf.starts_with(&J2K_SIGNATURE) {
+            Some(Codec::J2K)
+        } else {
+            None
+        }
+    }
+
+    pub(crate) fn to_i32(&self) -> i32 {
         match *self {
             Codec::J2K => ffi::CODEC_FORMAT_OPJ_CODEC_J2K,
             Codec::JP2 => ffi::CODEC_FORMAT_OPJ_CODEC_JP2,
@@ -86,7 +123,7 @@ impl ColorSpaceValue {
     }
 }
 
-unsafe fn get_default_decoder_parameters() -> ffi::opj_dparameters {
+pub(crate) fn default_decoder_parameters() -> ffi::opj_dparameters {
     let mut jp2_dparams = ffi::opj_dparameters {
         cp_reduce: 0,
         cp_layer: 0,
@@ -106,152 +143,298 @@ unsafe fn get_default_decoder_parameters() -> ffi::opj_dparameters {
         jpwl_max_tiles: 0,
         flags: 0,
     };
-    ffi::opj_set_default_decoder_parameters(&mut jp2_dparams);
+    unsafe { ffi::opj_set_default_decoder_parameters(&mut jp2_dparams) };
+    jp2_dparams
+}
+
+/// Apply the config and the extra `flags` to the default decoder parameters.
+pub(crate) fn decoder_parameters(
+    mut jp2_dparams: ffi::opj_dparameters,
+    config: &DecodeConfig,
+    flags: u32,
+) -> ffi::opj_dparameters {
+    jp2_dparams.cp_reduce = config.discard_level;
+    jp2_dparams.cp_layer = config.quality_layers;
+    jp2_dparams.flags |= flags;
     jp2_dparams
 }
 
+/// State that can be reused between consecutive decodes on the same thread.
+pub(crate) struct Scratch {
+    default_dparams: ffi::opj_dparameters,
+    /// Holds the contents of the last file read, so its allocation is reused for the next one.
+    file_buf: Vec<u8>,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Scratch {
+            default_dparams: default_decoder_parameters(),
+            file_buf: Vec::new(),
+        }
+    }
+}
+
 /// Divide by 2 to the power of b and round upwards.
 #[inline]
 fn ceil_div_pow2(a: u32, b: u32) -> u32 {
     (a + (1 << b) - 1) >> b
 }
 
-// jp2_stream: this function will take care of deleting this at the end.
-unsafe fn load_from_stream(
-    jp2_stream: *mut *mut c_void,
+// `flags` are or-ed into the decoder parameters, `convert` turns the decoded image into the
+// requested output.
+//
+// The marker segments of J2K and JP2 input are read for the report afterwards, if that fails the
+// image is still returned.
+fn load_from_memory<T, F>(
+    buf: &[u8],
     codec: Codec,
-    config: DecodeConfig,
+    config: &DecodeConfig,
     logger: Logger,
-) -> Result<DynamicImage, DecodeError> {
-    // Setup the codec.
-    let jp2_codec = ffi::opj_create_decompress(codec.to_i32());
-    if jp2_codec.is_null() {
-        ffi::opj_stream_destroy(jp2_stream);
-        return Err(DecodeError::FfiError("Codec instantiation failed."));
-    }
-    // TODO: What is actually a sensible key value pair here?
-    let logger = logger.new(o!("function"=>"decode jpeg2000 stream"));
-    let mut data = support::LogHandlerData::new(logger.clone());
-    let data_ptr: *mut support::LogHandlerData = &mut data;
-    let data_ptr = data_ptr as *mut c_void;
-    ffi::opj_set_info_handler(jp2_codec, Some(support::info_handler), data_ptr);
-    ffi::opj_set_warning_handler(jp2_codec, Some(support::warning_handler), data_ptr);
-    ffi::opj_set_error_handler(jp2_codec, Some(support::error_handler), data_ptr);
-
-    // Setup decoder.
-    let mut jp2_dparams = get_default_decoder_parameters();
-    jp2_dparams.cp_reduce = config.discard_level;
-    if ffi::opj_setup_decoder(jp2_codec, &mut jp2_dparams) != 1 {
-        ffi::opj_stream_destroy(jp2_stream);
-        ffi::opj_destroy_codec(jp2_codec);
-        return Err(DecodeError::FfiError("Setting up the decoder failed."));
-    }
+    scratch: &mut Scratch,
+    flags: u32,
+    convert: F,
+) -> Result<(T, DecodeReport), DecodeError>
+where
+    F: FnOnce(&Image, &OpjLogger) -> Result<T, DecodeError>,
+{
+    let probe = codec == Codec::J2K || codec == Codec::JP2;
+    let mut stream = Stream::from_memory(buf)?;
+    let mut decoder = Decoder::new(codec, Some(logger))?;
+    decoder.setup_with_parameters(decoder_parameters(scratch.default_dparams, config, flags))?;
 
     // Read header.
-    let mut jp2_image: *mut ffi::opj_image = null_mut();
-    if ffi::opj_read_header(jp2_stream, jp2_codec, &mut jp2_image) != 1 {
-        ffi::opj_stream_destroy(jp2_stream);
-        ffi::opj_destroy_codec(jp2_codec);
-        return Err(DecodeError::ReadHeader);
+    let mut report = DecodeReport::default();
+    let start = Instant::now();
+    let mut image = decoder.read_header(&mut stream)?;
+    report.header_time = start.elapsed();
+
+    if let Some(num_layers) = decoder.num_layers() {
+        report.layers = if config.quality_layers == 0 {
+            num_layers
+        } else {
+            config.quality_layers.min(num_layers)
+        };
     }
 
     // Decode the image.
-    ffi::opj_decode(jp2_codec, jp2_stream, jp2_image);
-    ffi::opj_stream_destroy(jp2_stream);
-
-    let color_space_raw = ColorSpaceValue::from_i32((*jp2_image).color_space);
-    let color_space = color_space_raw.determined();
-    let color_space: ColorSpace = if color_space.is_none() {
-        if color_space_raw == ColorSpaceValue::Unspecified {
-            match config.default_colorspace {
-                Some(cspace) => cspace,
-                None => return Err(DecodeError::UnspecifiedColorSpace),
+    let start = Instant::now();
+    decoder.decode(&mut stream, &mut image)?;
+    decoder.end_decompress(&mut stream)?;
+    report.decode_time = start.elapsed();
+
+    let data = decoder.handler_data();
+    // OpenJPEG succeeds without allocating the samples for a codestream ending after its main
+    // header.
+    let components = image.components().len();
+    if components == 0 || (0..components).any(|i| image.component_data(i).is_empty()) {
+        let mut messages = data.take_problems();
+        messages.push("the codestream has no image data".to_string());
+        return Err(DecodeError::Decode { messages });
+    }
+
+    if let Some(comp) = image.components().first() {
+        report.reduction = comp.factor;
+    }
+    data.fill_report(&mut report);
+    let logger = decoder.logger();
+    if probe {
+        match probe_memory(buf) {
+            Ok(info) => {
+                report.truncated |= info.truncated;
+                report.comments = info.comments;
+                report.modes = info.modes;
+                report.index_markers = info.index_markers;
             }
-        } else {
-            ffi::opj_destroy_codec(jp2_codec);
-            ffi::opj_image_destroy(jp2_image);
-            return Err(DecodeError::UnknownColorSpace);
+            Err(err) => logger.warn(format_args!("reading the marker segments failed: {}", err)),
         }
+    }
+    logger.finished(image.width(), image.height(), &report);
+
+    let output = convert(&image, logger)?;
+    Ok((output, report))
+}
+
+/// The color space of the decoded image, or the configured default if it has none.
+fn color_space(jp2_image: &Image, config: &DecodeConfig) -> Result<ColorSpace, DecodeError> {
+    let color_space_raw = ColorSpaceValue::from_i32(jp2_image.raw().color_space);
+    match color_space_raw.determined() {
+        Some(color_space) => Ok(color_space),
+        None if color_space_raw == ColorSpaceValue::Unspecified => config
+            .default_colorspace
+            .clone()
+            .ok_or(DecodeError::UnspecifiedColorSpace),
+        None => Err(DecodeError::UnknownColorSpace),
+    }
+}
+
+/// Scale a sample of a component to `bits` bits. Signed samples are moved up by half their
+/// range first, so their minimum becomes 0.
+fn scale_sample(value: i32, comp: &ffi::opj_image_comp, bits: u32) -> u16 {
+    let prec = comp.prec.clamp(1, 31);
+    let mut value = i64::from(value);
+    if comp.sgnd != 0 {
+        value += 1 << (prec - 1);
+    }
+    let max = (1i64 << prec) - 1;
+    let value = value.clamp(0, max);
+    let scaled = if prec >= bits {
+        value >> (prec - bits)
     } else {
-        color_space.unwrap()
+        value * ((1 << bits) - 1) / max
     };
-    info!(logger, "color space: {:?}", color_space);
-    info!(logger, "icc_profile_len: {}", (*jp2_image).icc_profile_len);
+    scaled as u16
+}
 
-    let width = (*jp2_image).x1 - (*jp2_image).x0;
-    let height = (*jp2_image).y1 - (*jp2_image).y0;
-    info!(logger, "width: {}, height: {}", width, height);
+/// Call `put` with the position and the RGBA samples of every pixel, scaled to `bits` bits.
+fn convert_pixels<F>(
+    jp2_image: &Image,
+    config: &DecodeConfig,
+    logger: &OpjLogger,
+    bits: u32,
+    mut put: F,
+) -> Result<(), DecodeError>
+where
+    F: FnMut(u32, u32, &ColorSpace, [u16; 4]),
+{
+    let color_space = color_space(jp2_image, config)?;
+    logger.info(format_args!("color space: {:?}", color_space));
+    logger.info(format_args!("icc_profile_len: {}", jp2_image.raw().icc_profile_len));
 
-    let mut comps: Vec<*mut ffi::opj_image_comp> = Vec::new();
-    let comps_len = (*jp2_image).numcomps;
-    for i in 0..comps_len {
-        comps.push((*jp2_image).comps.offset(i as isize));
-    }
+    let width = jp2_image.width();
+    let height = jp2_image.height();
+    logger.info(format_args!("width: {}, height: {}", width, height));
 
+    let comps = jp2_image.components();
     if comps.len() > color_convert::MAX_COMPONENTS {
-        ffi::opj_destroy_codec(jp2_codec);
-        ffi::opj_image_destroy(jp2_image);
         return Err(DecodeError::TooManyComponents(comps.len()));
     }
-    let mut image = DynamicImage::new_rgba8(width, height);
-    info!(logger, "number of components: {}", comps.len());
+    logger.info(format_args!("number of components: {}", comps.len()));
 
     // Copy the pixels.
-    let comp_width = (*comps[0]).w;
-    let factor = (*comps[0]).factor;
+    let comp_width = comps[0].w;
+    let factor = comps[0].factor;
     let width = ceil_div_pow2(width, factor);
     let height = ceil_div_pow2(height, factor);
 
     for y in (0..height).rev() {
         for x in 0..width {
-            //let index = (x + y * width) as isize;
-            let index = (y * comp_width + x) as isize;
+            let index = (y * comp_width + x) as usize;
 
-            // Note: Initialize the last component value to 255,
+            // Note: Initialize the last component value to the maximum,
             //       since this will be the alpha channel value in case
             //       there is actually no transparency.
-            let mut values = [0u8, 0, 0, 255];
-            for i in 0..comps.len() {
-                let data = (*comps[i]).data;
-                //assert!((*comps[i]).sgnd == 0); // TODO signed numbers?!
-                let ivalue: u8 = *data.offset(index) as u8;
-                values[i] = ivalue;
+            let mut values = [0, 0, 0, ((1u32 << bits) - 1) as u16];
+            for (i, value) in values.iter_mut().enumerate().take(comps.len()) {
+                let data = jp2_image.component_data(i);
+                *value = scale_sample(data[index], &comps[i], bits);
             }
 
-            image.unsafe_put_pixel(x, y, color_space.convert_to_rgba(values))
+            put(x, y, &color_space, values);
         }
     }
+    Ok(())
+}
 
-    ffi::opj_destroy_codec(jp2_codec);
-    ffi::opj_image_destroy(jp2_image);
-
+fn to_rgba_buffer(
+    jp2_image: &Image,
+    config: &DecodeConfig,
+    logger: &OpjLogger,
+) -> Result<RgbaBuffer, DecodeError> {
+    let mut image = RgbaBuffer::new(jp2_image.width(), jp2_image.height());
+    convert_pixels(jp2_image, config, logger, 8, |x, y, color_space, values| {
+        let values = [
+            values[0] as u8,
+            values[1] as u8,
+            values[2] as u8,
+            values[3] as u8,
+        ];
+        image.put_pixel(x, y, color_space.convert_to_rgba(values))
+    })?;
     Ok(image)
 }
 
+/// RGBA pixels, with 16-bit samples in native byte order if any component has more than 8 bits
+/// and with 8-bit samples otherwise.
+#[cfg(feature = "image")]
+pub(crate) struct RgbaPixels {
+    pub width: u32,
+    pub height: u32,
+    pub wide: bool,
+    pub data: Vec<u8>,
+}
+
+/// Decode an image for the `image` crate, keeping samples of more than 8 bits.
+#[cfg(feature = "image")]
+pub(crate) fn decode_rgba_pixels(
+    buf: &[u8],
+    codec: Codec,
+    config: &DecodeConfig,
+) -> Result<RgbaPixels, DecodeError> {
+    let mut scratch = Scratch::new();
+    let convert = |jp2_image: &Image, logger: &OpjLogger| {
+        let width = jp2_image.width();
+        let height = jp2_image.height();
+        let wide = jp2_image.components().iter().any(|comp| comp.prec > 8);
+        if !wide {
+            let data = to_rgba_buffer(jp2_image, config, logger)?.into_raw();
+            return Ok(RgbaPixels {
+                width,
+                height,
+                wide,
+                data,
+            });
+        }
+
+        let mut data = vec![0u8; width as usize * height as usize * 8];
+        convert_pixels(jp2_image, config, logger, 16, |x, y, color_space, values| {
+            let i = (y as usize * width as usize + x as usize) * 8;
+            let pixel = color_space.convert_to_rgba16(values);
+            for (c, sample) in pixel.iter().enumerate() {
+                data[i + c * 2..i + c * 2 + 2].copy_from_slice(&sample.to_ne_bytes());
+            }
+        })?;
+        Ok(RgbaPixels {
+            width,
+            height,
+            wide,
+            data,
+        })
+    };
+    load_from_memory(buf, codec, config, Logger::default(), &mut scratch, 0, convert)
+        .map(|(pixels, _)| pixels)
+}
+
 pub fn from_memory(
     buf: &[u8],
     codec: Codec,
     config: DecodeConfig,
     logger: Option<Logger>,
-) -> Result<DynamicImage, DecodeError> {
-    // TODO: In the future this should not copy the data into a vec but instead take a slice and
-    // store a slice in the NdUserdata with appropriate lifetime information.
-    let mut userdata = support::NdUserdata::new_input(buf);
-
-    let logger = logger.unwrap_or_else(|| Logger::root(slog::Discard, o!()));
-
-    unsafe {
-        let stream = ffi::opj_stream_default_create(1);
-        ffi::opj_stream_set_read_function(stream, Some(support::nd_opj_stream_read_fn));
-        ffi::opj_stream_set_write_function(stream, Some(support::nd_opj_stream_write_fn));
-        ffi::opj_stream_set_skip_function(stream, Some(support::nd_opj_stream_skip_fn));
-        ffi::opj_stream_set_seek_function(stream, Some(support::nd_opj_stream_seek_fn));
-
-        let userdata_ptr: *mut support::NdUserdata = &mut userdata;
-        ffi::opj_stream_set_user_data_length(stream, buf.len() as u64);
-        ffi::opj_stream_set_user_data(stream, userdata_ptr as *mut c_void, None);
-        load_from_stream(stream, codec, config, logger)
-    }
+) -> Result<RgbaBuffer, DecodeError> {
+    from_memory_with_report(buf, codec, config, logger).map(|(image, _)| image)
+}
+
+/// Like `from_memory`, but also returns a report about the decoding.
+pub fn from_memory_with_report(
+    buf: &[u8],
+    codec: Codec,
+    config: DecodeConfig,
+    logger: Option<Logger>,
+) -> Result<(RgbaBuffer, DecodeReport), DecodeError> {
+    let logger = logger.unwrap_or_default();
+    decode_memory(buf, codec, &config, logger, &mut Scratch::new())
+}
+
+pub(crate) fn decode_memory(
+    buf: &[u8],
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Logger,
+    scratch: &mut Scratch,
+) -> Result<(RgbaBuffer, DecodeReport), DecodeError> {
+    load_from_memory(buf, codec, config, logger, scratch, 0, |image, logger| {
+        to_rgba_buffer(image, config, logger)
+    })
 }
 
 // TODO: docs
@@ -260,12 +443,92 @@ pub fn from_file<S: Into<String>>(
     codec: Codec,
     config: DecodeConfig,
     logger: Option<Logger>,
-) -> Result<DynamicImage, DecodeError> {
-    let logger = logger.unwrap_or_else(|| Logger::root(slog::Discard, o!()));
+) -> Result<RgbaBuffer, DecodeError> {
+    from_file_with_report(file_name, codec, config, logger).map(|(image, _)| image)
+}
+
+/// Like `from_file`, but also returns a report about the decoding.
+pub fn from_file_with_report<S: Into<String>>(
+    file_name: S,
+    codec: Codec,
+    config: DecodeConfig,
+    logger: Option<Logger>,
+) -> Result<(RgbaBuffer, DecodeReport), DecodeError> {
+    let logger = logger.unwrap_or_default();
+    decode_file(
+        Path::new(&file_name.into()),
+        codec,
+        &config,
+        logger,
+        &mut Scratch::new(),
+    )
+}
+
+pub(crate) fn decode_file(
+    file_name: &Path,
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Logger,
+    scratch: &mut Scratch,
+) -> Result<(RgbaBuffer, DecodeReport), DecodeError> {
+    with_file(file_name, scratch, |buf, scratch| {
+        decode_memory(buf, codec, config, logger, scratch)
+    })
+}
+
+// The whole file is read into memory, so the marker segments are read from the same bytes.
+fn load_from_file<T, F>(
+    file_name: &Path,
+    codec: Codec,
+    config: &DecodeConfig,
+    logger: Logger,
+    scratch: &mut Scratch,
+    flags: u32,
+    convert: F,
+) -> Result<(T, DecodeReport), DecodeError>
+where
+    F: FnOnce(&Image, &OpjLogger) -> Result<T, DecodeError>,
+{
+    with_file(file_name, scratch, |buf, scratch| {
+        load_from_memory(buf, codec, config, logger, scratch, flags, convert)
+    })
+}
+
+/// Read a file into the buffer of `scratch` and pass its contents to `f`.
+fn with_file<T, F>(file_name: &Path, scratch: &mut Scratch, f: F) -> Result<T, DecodeError>
+where
+    F: FnOnce(&[u8], &mut Scratch) -> Result<T, DecodeError>,
+{
+    // The buffer is taken out while `f` borrows the rest of the scratch state.
+    let mut buf = mem::take(&mut scratch.file_buf);
+    buf.clear();
+    let result = match File::open(file_name).and_then(|mut file| file.read_to_end(&mut buf)) {
+        Ok(_) => f(&buf, scratch),
+        Err(_) => Err(DecodeError::OpenFile(file_name.display().to_string())),
+    };
+    scratch.file_buf = buf;
+    result
+}
+
+/// Read the comments and other marker segments of a J2K codestream or a JP2 file without
+/// decoding the image.
+pub fn probe_memory(buf: &[u8]) -> Result<CodestreamInfo, DecodeError> {
+    probe(&mut Cursor::new(buf))
+}
+
+/// Like `probe_memory`, but reading from a file.
+pub fn probe_file<S: Into<String>>(file_name: S) -> Result<CodestreamInfo, DecodeError> {
+    probe(&mut File::open(file_name.into())?)
+}
 
-    unsafe {
-        let f = CString::new(file_name.into())?;
-        let jp2_stream = ffi::opj_stream_create_default_file_stream(f.as_ptr(), 1);
-        load_from_stream(jp2_stream, codec, config, logger)
+fn probe<R: Read + Seek>(reader: &mut R) -> Result<CodestreamInfo, DecodeError> {
+    let mut signature = Vec::with_capacity(JP2_SIGNATURE.len());
+    reader.by_ref().take(JP2_SIGNATURE.len() as u64).read_to_end(&mut signature)?;
+    reader.rewind()?;
+    if Codec::detect(&signature) == Some(Codec::JP2)
+        && codestream::seek_jp2c(reader)?.is_none()
+    {
+        return Err(DecodeError::InvalidCodestream("the file has no codestream box"));
     }
+    Ok(codestream::scan(reader)?)
 }
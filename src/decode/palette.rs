@@ -0,0 +1,197 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{load_from_file, load_from_memory, Codec, DecodeConfig, Scratch, JP2_SIGNATURE};
+use boxes::{self, BoxContent, Boxes};
+use error::DecodeError;
+use logging::Logger;
+use openjpeg2_sys as ffi;
+use raw::Image;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+/// Bit depth and signedness of one palette column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteColumn {
+    pub precision: u8,
+    pub signed: bool,
+}
+
+/// The palette table of a JP2 `pclr` box.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub columns: Vec<PaletteColumn>,
+    /// One entry per palette index, each holding one value per column.
+    pub entries: Vec<Vec<i32>>,
+}
+
+/// A palettised image which was decoded without applying its palette.
+#[derive(Clone, Debug)]
+pub struct IndexedImage {
+    pub width: u32,
+    pub height: u32,
+    /// Palette indices in row-major order, one per pixel.
+    pub indices: Vec<u16>,
+    pub palette: Palette,
+}
+
+/// Decode a palettised JP2 image from memory into palette indices and the palette table.
+///
+/// Use `from_memory` to get the palette-expanded colour image instead.
+pub fn indexed_from_memory(
+    buf: &[u8],
+    codec: Codec,
+    config: DecodeConfig,
+    logger: Option<Logger>,
+) -> Result<IndexedImage, DecodeError> {
+    // A raw codestream has no boxes, so it has no palette either.
+    let jp2h = if buf.starts_with(&JP2_SIGNATURE) {
+        Boxes::new(buf).find(boxes::HEADER)?
+    } else {
+        None
+    };
+    let (palette, component) = match jp2h {
+        Some(jp2h) => parse_jp2h(jp2h.children())?,
+        None => return Err(DecodeError::NoPalette),
+    };
+    let logger = logger.unwrap_or_default();
+
+    load_from_memory(
+        buf,
+        codec,
+        &config,
+        logger,
+        &mut Scratch::new(),
+        ffi::OPJ_DPARAMETERS_IGNORE_PCLR_CMAP_CDEF_FLAG,
+        |image, _| to_indexed_image(image, palette, component),
+    )
+    .map(|(image, _)| image)
+}
+
+/// Decode a palettised JP2 file into palette indices and the palette table.
+///
+/// Use `from_file` to get the palette-expanded colour image instead.
+pub fn indexed_from_file<S: Into<String>>(
+    file_name: S,
+    codec: Codec,
+    config: DecodeConfig,
+    logger: Option<Logger>,
+) -> Result<IndexedImage, DecodeError> {
+    let file_name = file_name.into();
+    let jp2h = read_jp2h(&mut File::open(&file_name)?)?;
+    let (palette, component) = match jp2h {
+        Some(jp2h) => parse_jp2h(Boxes::new(&jp2h))?,
+        None => return Err(DecodeError::NoPalette),
+    };
+    let logger = logger.unwrap_or_default();
+
+    load_from_file(
+        Path::new(&file_name),
+        codec,
+        &config,
+        logger,
+        &mut Scratch::new(),
+        ffi::OPJ_DPARAMETERS_IGNORE_PCLR_CMAP_CDEF_FLAG,
+        |image, _| to_indexed_image(image, palette, component),
+    )
+    .map(|(image, _)| image)
+}
+
+fn to_indexed_image(
+    jp2_image: &Image,
+    palette: Palette,
+    component: u32,
+) -> Result<IndexedImage, DecodeError> {
+    let component = component as usize;
+    let comp = match jp2_image.components().get(component) {
+        Some(comp) => comp,
+        None => {
+            return Err(DecodeError::InvalidPalette(
+                "the palette refers to a missing component",
+            ))
+        }
+    };
+    let top = palette.entries.len() as i32 - 1;
+
+    // Out of range indices are clamped, the same way OpenJPEG does when applying the palette.
+    let indices = jp2_image
+        .component_data(component)
+        .iter()
+        .map(|index| (*index).max(0).min(top) as u16)
+        .collect();
+
+    Ok(IndexedImage {
+        width: comp.w,
+        height: comp.h,
+        indices,
+        palette,
+    })
+}
+
+/// Parse the `pclr` and `cmap` boxes of a `jp2h` superbox.
+///
+/// Returns the palette and the codestream component holding the palette indices.
+fn parse_jp2h(jp2h: Boxes) -> Result<(Palette, u32), DecodeError> {
+    let (mut palette, mut mapping) = (None, None);
+    for jp2_box in jp2h {
+        let jp2_box = jp2_box?;
+        if jp2_box.box_type != boxes::PALETTE && jp2_box.box_type != boxes::COMPONENT_MAPPING {
+            continue;
+        }
+        match jp2_box.parse()? {
+            BoxContent::Palette(pclr) if palette.is_none() => palette = Some(pclr),
+            BoxContent::ComponentMapping(cmap) if mapping.is_none() => mapping = Some(cmap),
+            _ => {}
+        }
+    }
+    let palette = match palette {
+        Some(palette) => palette,
+        None => return Err(DecodeError::NoPalette),
+    };
+
+    // Without a component mapping the indices are stored in the first component.
+    let mut component = 0;
+    if let Some(mapping) = mapping {
+        match mapping.iter().find(|channel| channel.palette_column.is_some()) {
+            Some(channel) => component = u32::from(channel.component),
+            None => return Err(DecodeError::NoPalette),
+        }
+    }
+
+    Ok((palette, component))
+}
+
+/// Read the payload of the `jp2h` box of a JP2 file without reading the codestream.
+fn read_jp2h<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<u8>>, DecodeError> {
+    let header = match boxes::seek_box(reader, boxes::HEADER)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let mut payload = Vec::new();
+    match header.payload_len {
+        Some(payload_len) => {
+            reader.by_ref().take(payload_len).read_to_end(&mut payload)?;
+            if payload.len() as u64 != payload_len {
+                return Err(DecodeError::InvalidPalette("truncated jp2h box"));
+            }
+        }
+        None => {
+            reader.read_to_end(&mut payload)?;
+        }
+    }
+    Ok(Some(payload))
+}
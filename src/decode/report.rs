@@ -0,0 +1,48 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use codestream::{CodingModes, Comment, IndexMarkers};
+use std::time::Duration;
+
+/// Diagnostics collected while decoding a single image.
+#[derive(Clone, Debug, Default)]
+pub struct DecodeReport {
+    /// Warnings reported by OpenJPEG.
+    pub warnings: Vec<String>,
+    /// Info messages reported by OpenJPEG.
+    pub infos: Vec<String>,
+    /// Wall-clock time spent reading the main header.
+    pub header_time: Duration,
+    /// Wall-clock time spent decoding the image data.
+    pub decode_time: Duration,
+    /// Number of resolution levels that were actually discarded.
+    pub reduction: u32,
+    /// Number of quality layers that were actually decoded.
+    pub layers: u32,
+    /// Whether the codestream ended before its end of codestream marker.
+    ///
+    /// This is set when OpenJPEG reports a codestream that ends early, or when the codestream ends
+    /// before the end of a tile-part given by its length. A last tile-part with a length of 0 runs
+    /// to the end of the codestream, so a cut in its data goes unnoticed. Most codestreams ending
+    /// within a tile-part fail to decode instead, see `DecodeError::is_truncated`.
+    pub truncated: bool,
+    /// The comments of the main header and the tile-part headers.
+    pub comments: Vec<Comment>,
+    /// The error resilience markers and code-block coding modes the codestream uses.
+    pub modes: CodingModes,
+    /// The index markers found in the codestream.
+    pub index_markers: IndexMarkers,
+}
@@ -16,19 +16,85 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use slog::Logger;
+use super::DecodeReport;
+use logging::OpjLogger;
 use std::ffi::CStr;
+use std::io::{self, Seek, SeekFrom, Write};
 use std::os::raw::{c_char, c_void};
 use std::slice;
 
+/// Whether a message of OpenJPEG is about a codestream which ends early.
+pub fn is_truncation(message: &str) -> bool {
+    [
+        "does not end with EOC",
+        "Stream too short",
+        "Tile part length size inconsistent with stream length",
+        "the codestream has no image data",
+    ]
+    .iter()
+    .any(|m| message.contains(m))
+}
+
 pub struct LogHandlerData {
-    logger: Logger,
+    logger: OpjLogger,
+    /// Error messages reported by OpenJPEG, so they can be returned to the caller.
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    infos: Vec<String>,
 }
 
 impl LogHandlerData {
-    pub fn new(logger: Logger) -> Self {
-        LogHandlerData { logger: logger }
+    pub fn new(logger: OpjLogger) -> Self {
+        LogHandlerData {
+            logger,
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            infos: Vec::new(),
+        }
+    }
+
+    pub fn logger(&self) -> &OpjLogger {
+        &self.logger
+    }
+
+    /// Take all error messages collected so far.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        ::std::mem::take(&mut self.errors)
     }
+
+    /// Take all error and warning messages collected so far.
+    pub fn take_problems(&mut self) -> Vec<String> {
+        let mut problems = self.take_errors();
+        problems.append(&mut self.warnings);
+        problems
+    }
+
+    /// Move the collected warnings and info messages into the report.
+    pub fn fill_report(&mut self, report: &mut DecodeReport) {
+        // OpenJPEG only warns about a missing end of codestream marker and decodes what is there,
+        // a codestream ending within a tile-part is usually an error which fails the decode.
+        report.truncated = self
+            .warnings
+            .iter()
+            .chain(&self.errors)
+            .any(|message| is_truncation(message));
+        report.warnings = ::std::mem::take(&mut self.warnings);
+        report.infos = ::std::mem::take(&mut self.infos);
+    }
+}
+
+/// A sink the output of a stream can be written to.
+pub trait WriteSeek: Write + Seek {}
+
+impl<T: Write + Seek> WriteSeek for T {}
+
+/// An output stream writing to a sink, the offsets of OpenJPEG are relative to its position
+/// when the stream was created.
+struct Sink<'a> {
+    writer: &'a mut dyn WriteSeek,
+    start: u64,
+    /// The first error of the writer, OpenJPEG only learns that writing failed.
+    error: Option<io::Error>,
 }
 
 pub struct NdUserdata<'a> {
@@ -36,6 +102,7 @@ pub struct NdUserdata<'a> {
     offset: usize,
     output: Vec<u8>,
     input: &'a [u8],
+    sink: Option<Sink<'a>>,
 }
 
 impl<'a> NdUserdata<'a> {
@@ -45,8 +112,79 @@ impl<'a> NdUserdata<'a> {
             offset: 0,
             output: Vec::new(),
             input: data,
+            sink: None,
+        }
+    }
+
+    /// An output stream writing to `writer` from its current position on.
+    pub fn new_sink(writer: &'a mut dyn WriteSeek) -> io::Result<Self> {
+        let start = writer.stream_position()?;
+        Ok(NdUserdata {
+            input_stream: false,
+            offset: 0,
+            output: Vec::new(),
+            input: &[],
+            sink: Some(Sink {
+                writer,
+                start,
+                error: None,
+            }),
+        })
+    }
+
+    pub fn flush_sink(&mut self) -> io::Result<()> {
+        match self.sink {
+            Some(ref mut sink) => sink.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Take the first error of the sink.
+    pub fn take_sink_error(&mut self) -> Option<io::Error> {
+        self.sink.as_mut().and_then(|sink| sink.error.take())
+    }
+}
+
+impl<'a> Sink<'a> {
+    fn write(&mut self, data: &[u8]) -> bool {
+        let result = self.writer.write_all(data);
+        self.check(result)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> bool {
+        let result = self.writer.seek(position).map(|_| ());
+        self.check(result)
+    }
+
+    fn check(&mut self, result: io::Result<()>) -> bool {
+        match result {
+            Ok(()) => true,
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(err);
+                }
+                false
+            }
+        }
+    }
+}
+
+impl NdUserdata<'static> {
+    pub fn new_output() -> Self {
+        NdUserdata {
+            input_stream: false,
+            offset: 0,
+            output: Vec::new(),
+            input: &[],
+            sink: None,
         }
     }
+
+    /// Take everything written to an output stream so far.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.offset = 0;
+        ::std::mem::take(&mut self.output)
+    }
 }
 
 pub unsafe extern "C" fn nd_opj_stream_read_fn(
@@ -67,9 +205,8 @@ pub unsafe extern "C" fn nd_opj_stream_read_fn(
     }
 
     if (*userdata).input.is_empty() || p_buffer.is_null() || n_read == 0 || n_byteleft == 0 {
-        // TODO: The original returned -1 here,
-        // but for some reason our signature is usize...
-        return 0;
+        // OpenJPEG's end of stream value for `OPJ_SIZE_T`, returning 0 makes it retry forever.
+        return usize::MAX;
     }
 
     let target = slice::from_raw_parts_mut(p_buffer as *mut u8, n_read);
@@ -91,19 +228,46 @@ pub unsafe extern "C" fn nd_opj_stream_write_fn(
 
     let buffer = p_buffer as *mut u8;
 
-    (*userdata)
-        .output
-        .reserve((*userdata).output.len() + p_nb_bytes);
-    (*userdata)
-        .output
-        .extend_from_slice(slice::from_raw_parts(buffer, p_nb_bytes));
+    if let Some(ref mut sink) = (*userdata).sink {
+        return if sink.write(slice::from_raw_parts(buffer, p_nb_bytes)) {
+            p_nb_bytes
+        } else {
+            // OpenJPEG's error value for `OPJ_SIZE_T`.
+            usize::MAX
+        };
+    }
+
+    // Writes happen at the current offset, since the JP2 writer seeks back to fill in the length
+    // of the codestream box.
+    let offset = (*userdata).offset;
+    let end = offset + p_nb_bytes;
+    if (*userdata).output.len() < end {
+        (*userdata).output.resize(end, 0);
+    }
+    (*userdata).output[offset..end].copy_from_slice(slice::from_raw_parts(buffer, p_nb_bytes));
+    (*userdata).offset = end;
 
     p_nb_bytes
 }
 
 pub unsafe extern "C" fn nd_opj_stream_skip_fn(p_nb_bytes: i64, p_user_data: *mut c_void) -> i64 {
     let userdata = p_user_data as *mut NdUserdata;
-    assert!((*userdata).input_stream);
+    if let Some(ref mut sink) = (*userdata).sink {
+        return if sink.seek(SeekFrom::Current(p_nb_bytes)) {
+            p_nb_bytes
+        } else {
+            -1
+        };
+    }
+    if !(*userdata).input_stream {
+        // Skipped bytes of the output are zero filled.
+        (*userdata).offset += p_nb_bytes as usize;
+        let offset = (*userdata).offset;
+        if (*userdata).output.len() < offset {
+            (*userdata).output.resize(offset, 0);
+        }
+        return p_nb_bytes;
+    }
 
     let n_imgsize = (*userdata).input.len();
     let n_byteleft = (n_imgsize - (*userdata).offset) as i64;
@@ -115,12 +279,20 @@ pub unsafe extern "C" fn nd_opj_stream_skip_fn(p_nb_bytes: i64, p_user_data: *mu
     }
 
     (*userdata).offset += n_skip as usize;
-    (*userdata).offset as i64
+    // The number of bytes actually skipped, not the new offset.
+    n_skip
 }
 
 pub unsafe extern "C" fn nd_opj_stream_seek_fn(p_nb_bytes: i64, p_user_data: *mut c_void) -> i32 {
     let userdata = p_user_data as *mut NdUserdata;
-    assert!((*userdata).input_stream);
+    if let Some(ref mut sink) = (*userdata).sink {
+        let position = sink.start + p_nb_bytes as u64;
+        return sink.seek(SeekFrom::Start(position)) as i32;
+    }
+    if !(*userdata).input_stream {
+        (*userdata).offset = p_nb_bytes as usize;
+        return 1;
+    }
 
     let n_imgsize = (*userdata).input.len();
     let n_seek = p_nb_bytes as usize;
@@ -135,15 +307,21 @@ pub unsafe extern "C" fn nd_opj_stream_seek_fn(p_nb_bytes: i64, p_user_data: *mu
 
 pub unsafe extern "C" fn info_handler(msg: *const c_char, p_data: *mut c_void) {
     let data = p_data as *mut LogHandlerData;
-    info!((*data).logger, "{}", CStr::from_ptr(msg).to_string_lossy());
+    let msg = CStr::from_ptr(msg).to_string_lossy();
+    (*data).logger.info(format_args!("{}", msg));
+    (*data).infos.push(msg.trim_end().to_string());
 }
 
 pub unsafe extern "C" fn warning_handler(msg: *const c_char, p_data: *mut c_void) {
     let data = p_data as *mut LogHandlerData;
-    warn!((*data).logger, "{}", CStr::from_ptr(msg).to_string_lossy());
+    let msg = CStr::from_ptr(msg).to_string_lossy();
+    (*data).logger.warn(format_args!("{}", msg));
+    (*data).warnings.push(msg.trim_end().to_string());
 }
 
 pub unsafe extern "C" fn error_handler(msg: *const c_char, p_data: *mut c_void) {
     let data = p_data as *mut LogHandlerData;
-    error!((*data).logger, "{}", CStr::from_ptr(msg).to_string_lossy());
+    let msg = CStr::from_ptr(msg).to_string_lossy();
+    (*data).logger.error(format_args!("{}", msg));
+    (*data).errors.push(msg.trim_end().to_string());
 }
@@ -0,0 +1,245 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::Component;
+use decode::{ColorSpace, JP2_SIGNATURE};
+use error::EncodeError;
+
+/// The UUID of XMP metadata packets, BE7ACFCB-97A9-42E8-9C71-999491E3AFAC.
+pub const XMP_UUID: [u8; 16] = [
+    0xbe, 0x7a, 0xcf, 0xcb, 0x97, 0xa9, 0x42, 0xe8, 0x9c, 0x71, 0x99, 0x94, 0x91, 0xe3, 0xaf, 0xac,
+];
+
+/// The boxes of a JP2 file around the codestream, written by this crate instead of OpenJPEG.
+///
+/// The image header (`ihdr`), the bit depths of the components (`bpcc`) and the channel
+/// definitions of alpha channels (`cdef`) are derived from the image.
+#[derive(Clone, Debug, Default)]
+pub struct Jp2Boxes {
+    /// The colour specification (`colr`), by default the color space of the image.
+    pub color: Option<ColorSpecification>,
+    /// The resolution the image was captured at (`resc`).
+    pub capture_resolution: Option<GridResolution>,
+    /// The resolution the image should be displayed at (`resd`).
+    pub display_resolution: Option<GridResolution>,
+    /// XML boxes, written after the header.
+    pub xml: Vec<String>,
+    /// UUID boxes, written after the XML boxes.
+    pub uuids: Vec<UuidBox>,
+}
+
+/// The colour space of the decoded samples.
+#[derive(Clone, Debug)]
+pub enum ColorSpecification {
+    /// An enumerated colour space. Only `SRGB`, `GRAY` and `SYCC` are part of JP2, `CMYK` and
+    /// `EYCC` are JPX extensions and rejected.
+    Enumerated(ColorSpace),
+    /// An embedded ICC profile, which JP2 restricts to monochrome and three-component
+    /// matrix-based profiles.
+    Icc(Vec<u8>),
+}
+
+/// Horizontal and vertical grid points per metre.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridResolution {
+    pub horizontal: f64,
+    pub vertical: f64,
+}
+
+impl GridResolution {
+    /// A resolution given in dots per inch.
+    pub fn from_dpi(horizontal: f64, vertical: f64) -> Self {
+        GridResolution {
+            horizontal: horizontal / 0.0254,
+            vertical: vertical / 0.0254,
+        }
+    }
+}
+
+/// Vendor data identified by a UUID, e.g. XMP metadata with `XMP_UUID`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UuidBox {
+    pub uuid: [u8; 16],
+    pub data: Vec<u8>,
+}
+
+/// Write everything of a JP2 file before the codestream box.
+pub(crate) fn write_header(
+    width: u32,
+    height: u32,
+    color_space: &Option<ColorSpace>,
+    components: &[Component],
+    boxes: &Jp2Boxes,
+) -> Result<Vec<u8>, EncodeError> {
+    let mut output = JP2_SIGNATURE.to_vec();
+    // The brand, the minor version and the compatibility list.
+    write_box(&mut output, b"ftyp", b"jp2 \0\0\0\0jp2 ");
+
+    // The same bit depth for all components is given in the image header, otherwise each one
+    // is listed in a bpcc box.
+    let depths: Vec<u8> = components
+        .iter()
+        .map(|component| (component.precision - 1) | (u8::from(component.signed) << 7))
+        .collect();
+    let mixed = depths.iter().any(|depth| *depth != depths[0]);
+
+    let mut ihdr = Vec::with_capacity(14);
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&(components.len() as u16).to_be_bytes());
+    // The compression type is always 7, the colour space is known and there's no IPR box.
+    ihdr.extend_from_slice(&[if mixed { 255 } else { depths[0] }, 7, 0, 0]);
+
+    let mut jp2h = Vec::new();
+    write_box(&mut jp2h, b"ihdr", &ihdr);
+    if mixed {
+        write_box(&mut jp2h, b"bpcc", &depths);
+    }
+    write_box(&mut jp2h, b"colr", &color_box(color_space, &boxes.color)?);
+
+    // Only the last component of gray and sRGB images can be an alpha channel, it applies to
+    // the whole image.
+    let has_alpha = matches!(
+        (color_space, components.len()),
+        (&Some(ColorSpace::GRAY), 2) | (&Some(ColorSpace::SRGB), 4)
+    );
+    if has_alpha {
+        let channels = components.len() as u16;
+        let mut cdef = channels.to_be_bytes().to_vec();
+        for channel in 0..channels {
+            let (kind, association) = if channel == channels - 1 {
+                (1u16, 0u16)
+            } else {
+                (0, channel + 1)
+            };
+            cdef.extend_from_slice(&channel.to_be_bytes());
+            cdef.extend_from_slice(&kind.to_be_bytes());
+            cdef.extend_from_slice(&association.to_be_bytes());
+        }
+        write_box(&mut jp2h, b"cdef", &cdef);
+    }
+
+    if boxes.capture_resolution.is_some() || boxes.display_resolution.is_some() {
+        let mut res = Vec::new();
+        if let Some(ref resolution) = boxes.capture_resolution {
+            write_box(&mut res, b"resc", &resolution_box(resolution)?);
+        }
+        if let Some(ref resolution) = boxes.display_resolution {
+            write_box(&mut res, b"resd", &resolution_box(resolution)?);
+        }
+        write_box(&mut jp2h, b"res ", &res);
+    }
+    write_box(&mut output, b"jp2h", &jp2h);
+
+    for xml in &boxes.xml {
+        write_box(&mut output, b"xml ", xml.as_bytes());
+    }
+    for uuid in &boxes.uuids {
+        let mut data = uuid.uuid.to_vec();
+        data.extend_from_slice(&uuid.data);
+        write_box(&mut output, b"uuid", &data);
+    }
+    Ok(output)
+}
+
+/// Append a box, with an extended length if it doesn't fit 32 bits.
+pub(crate) fn write_box(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    let len = payload.len() as u64 + 8;
+    if len > u64::from(u32::MAX) {
+        output.extend_from_slice(&1u32.to_be_bytes());
+        output.extend_from_slice(kind);
+        output.extend_from_slice(&(len + 8).to_be_bytes());
+    } else {
+        output.extend_from_slice(&(len as u32).to_be_bytes());
+        output.extend_from_slice(kind);
+    }
+    output.extend_from_slice(payload);
+}
+
+fn color_box(
+    color_space: &Option<ColorSpace>,
+    color: &Option<ColorSpecification>,
+) -> Result<Vec<u8>, EncodeError> {
+    let enumerated = match (color, color_space) {
+        (&Some(ColorSpecification::Icc(ref profile)), _) => {
+            if profile.is_empty() {
+                return Err(EncodeError::InvalidConfig("the ICC profile is empty"));
+            }
+            // The restricted ICC method, precedence and approximation aren't used by JP2.
+            let mut colr = vec![2, 0, 0];
+            colr.extend_from_slice(profile);
+            return Ok(colr);
+        }
+        (&Some(ColorSpecification::Enumerated(ref color_space)), _)
+        | (&None, &Some(ref color_space)) => color_space,
+        (&None, &None) => {
+            return Err(EncodeError::InvalidConfig(
+                "JP2 files need a color space or an ICC profile",
+            ))
+        }
+    };
+    let value: u32 = match *enumerated {
+        ColorSpace::SRGB => 16,
+        ColorSpace::GRAY => 17,
+        ColorSpace::SYCC => 18,
+        // The file is branded as JP2, whose readers don't have to know the JPX colour spaces.
+        ColorSpace::CMYK | ColorSpace::EYCC => {
+            return Err(EncodeError::InvalidConfig(
+                "CMYK and e-sYCC are JPX colour spaces, JP2 files can't use them",
+            ))
+        }
+    };
+    let mut colr = vec![1, 0, 0];
+    colr.extend_from_slice(&value.to_be_bytes());
+    Ok(colr)
+}
+
+/// The vertical and then horizontal resolution, each as numerator and denominator, followed by
+/// their decimal exponents.
+fn resolution_box(resolution: &GridResolution) -> Result<Vec<u8>, EncodeError> {
+    let (vertical, vertical_exponent) = resolution_value(resolution.vertical)?;
+    let (horizontal, horizontal_exponent) = resolution_value(resolution.horizontal)?;
+    let mut data = Vec::with_capacity(10);
+    data.extend_from_slice(&vertical.to_be_bytes());
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.extend_from_slice(&horizontal.to_be_bytes());
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.push(vertical_exponent as u8);
+    data.push(horizontal_exponent as u8);
+    Ok(data)
+}
+
+/// Split a resolution into the largest 16-bit numerator and a decimal exponent.
+fn resolution_value(value: f64) -> Result<(u16, i8), EncodeError> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(EncodeError::InvalidConfig("a resolution has to be positive"));
+    }
+    let max = f64::from(u16::MAX) + 0.5;
+    let mut exponent = 0i32;
+    let mut scaled = value;
+    while scaled >= max {
+        scaled /= 10.0;
+        exponent += 1;
+    }
+    while scaled * 10.0 < max && exponent > i32::from(i8::MIN) {
+        scaled *= 10.0;
+        exponent -= 1;
+    }
+    if exponent > i32::from(i8::MAX) || scaled < 0.5 {
+        return Err(EncodeError::InvalidConfig("a resolution is out of range"));
+    }
+    Ok((scaled.round() as u16, exponent as i8))
+}
@@ -0,0 +1,1053 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use codestream;
+use decode::{Codec, ColorSpace};
+use error::EncodeError;
+#[cfg(feature = "image")]
+use image::{DynamicImage, GenericImageView};
+use logging::Logger;
+use openjpeg2_sys as ffi;
+use raw::{Encoder, Image, Stream};
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::mem;
+use std::os::raw::c_char;
+
+pub use codestream::{CodingModes, IndexMarkers};
+
+mod planes;
+#[cfg(feature = "image")]
+pub(crate) use self::planes::deinterleave;
+pub use self::planes::{Component, Plane, Planes, Samples};
+
+mod jp2;
+pub use self::jp2::{ColorSpecification, GridResolution, Jp2Boxes, UuidBox, XMP_UUID};
+
+mod tiles;
+pub use self::tiles::{TileEncoder, TileRect, TiledImage};
+
+/// The number of decomposition levels OpenJPEG uses by default.
+const DEFAULT_LEVELS: u32 = 5;
+
+/// The maximum number of decomposition levels of JPEG 2000 Part-1.
+const MAX_LEVELS: u32 = 32;
+
+/// The maximum number of tiles of JPEG 2000 Part-1.
+const MAX_TILES: u64 = 65_535;
+
+/// The maximum number of progression order changes OpenJPEG accepts, counted over all tiles.
+const MAX_PROGRESSION_CHANGES: usize = 32;
+
+/// The code-block size OpenJPEG uses by default.
+const DEFAULT_CODE_BLOCK_SIZE: (u32, u32) = (64, 64);
+
+/// The maximum number of quality layers OpenJPEG supports.
+const MAX_LAYERS: usize = 100;
+
+/// The maximum number of components of JPEG 2000 Part-1.
+const MAX_COMPONENTS: usize = 16_384;
+
+/// The largest texture the Second Life and OpenSim viewers accept.
+const MAX_TEXTURE_SIZE: u32 = 2048;
+
+/// Options for encoding, the default produces a lossless image.
+#[derive(Clone, Debug, Default)]
+pub struct EncodeConfig {
+    /// Text comments written into COM markers of the main header, they can only hold Latin-1
+    /// characters. Without comments OpenJPEG writes one naming its version.
+    pub comments: Vec<String>,
+    /// The wavelet transform, only the reversible one allows lossless compression.
+    pub transform: Transform,
+    /// The quality layers from the lowest to the highest quality, no layers is the same as a
+    /// single `QualityLayer::Lossless` layer.
+    ///
+    /// All layers have to be given either by ratio or by PSNR, and each layer has to improve on
+    /// the previous one.
+    pub quality_layers: Vec<QualityLayer>,
+    /// Split the image into tiles, by default the whole image is a single tile.
+    pub tiles: Option<Tiles>,
+    /// Number of wavelet decomposition levels, at most 32. By default 5 levels are used, or
+    /// fewer for images too small for that.
+    pub decomposition_levels: Option<u32>,
+    /// Width and height of the code-blocks, powers of two between 4 and 1024 with at most 4096
+    /// samples per block. The default is 64x64.
+    pub code_block_size: Option<(u32, u32)>,
+    /// Precinct width and height per resolution, starting with the highest resolution. The
+    /// sizes have to be powers of two up to 2^15, and resolutions without a size of their own
+    /// use half the size of the next higher one. By default each resolution is a single
+    /// precinct.
+    pub precinct_sizes: Vec<(u32, u32)>,
+    /// Position of the image on the reference grid.
+    pub image_offset: (u32, u32),
+    /// The order of the packets in the codestream.
+    pub progression_order: ProgressionOrder,
+    /// Progression order changes (POC markers), each one applies to every tile.
+    ///
+    /// OpenJPEG supports at most 32 changes, counted over all tiles.
+    pub progression_changes: Vec<ProgressionChange>,
+    /// Split each tile into several tile-parts.
+    pub tile_parts: Option<TilePartDivision>,
+    /// The transform decorrelating the components before the wavelet transform.
+    pub component_transform: ComponentTransform,
+    /// Check the image and the config against the constraints of a profile.
+    pub profile: Option<Profile>,
+    /// Encode one whole component as region of interest. The linked OpenJPEG can't do this yet,
+    /// so any region fails the encode, see `RegionOfInterest`.
+    pub region_of_interest: Option<RegionOfInterest>,
+    /// Error resilience markers and code-block coding modes, all off by default.
+    ///
+    /// BYPASS, TERMALL and SEGSYM fail the encode, as the encoder of the linked OpenJPEG (2.3)
+    /// overflows its buffers with them. Decoding reports them as usual.
+    pub coding_modes: CodingModes,
+    /// Index markers added to the finished codestream.
+    ///
+    /// Packets are found by their SOP markers, so they are written while encoding and removed
+    /// again unless `coding_modes` asks for them.
+    pub index_markers: IndexMarkers,
+    /// Write the boxes of JP2 files here instead of leaving them to OpenJPEG, to choose the
+    /// colour specification and add resolution, XML and UUID boxes. Only allowed with
+    /// `Codec::JP2`.
+    pub jp2_boxes: Option<Jp2Boxes>,
+}
+
+impl EncodeConfig {
+    /// The texture profile of the Second Life and OpenSim viewers: five irreversible layers at
+    /// ratios 1920, 480, 120, 30 and 10.
+    ///
+    /// Textures have to be 8-bit with sides that are powers of two up to 2048.
+    pub fn second_life_texture() -> Self {
+        EncodeConfig {
+            transform: Transform::Irreversible,
+            quality_layers: vec![
+                QualityLayer::Ratio(1920.0),
+                QualityLayer::Ratio(480.0),
+                QualityLayer::Ratio(120.0),
+                QualityLayer::Ratio(30.0),
+                QualityLayer::Ratio(10.0),
+            ],
+            profile: Some(Profile::SecondLifeTexture),
+            ..EncodeConfig::default()
+        }
+    }
+
+    /// The DCI 2K digital cinema profile at 24 frames per second, for 12-bit RGB (or XYZ)
+    /// images of at most 2048x1080.
+    pub fn cinema_2k() -> Self {
+        EncodeConfig {
+            decomposition_levels: Some(5),
+            ..EncodeConfig::cinema(Profile::Cinema2K)
+        }
+    }
+
+    /// The DCI 4K digital cinema profile at 24 frames per second, for 12-bit RGB (or XYZ)
+    /// images of at most 4096x2160.
+    pub fn cinema_4k() -> Self {
+        EncodeConfig {
+            decomposition_levels: Some(6),
+            ..EncodeConfig::cinema(Profile::Cinema4K)
+        }
+    }
+
+    fn cinema(profile: Profile) -> Self {
+        EncodeConfig {
+            transform: Transform::Irreversible,
+            // OpenJPEG lowers the quality to stay within the bit rate of the profile.
+            quality_layers: vec![QualityLayer::Ratio(1.0)],
+            code_block_size: Some((32, 32)),
+            // OpenJPEG sets the precincts of the profile itself.
+            progression_order: ProgressionOrder::CPRL,
+            tile_parts: Some(TilePartDivision::Component),
+            profile: Some(profile),
+            ..EncodeConfig::default()
+        }
+    }
+
+    /// Lossless encoding for archives, meant to be written as JP2.
+    ///
+    /// Large precincts at the higher resolutions and a resolution first progression order
+    /// allow viewers to decode reduced versions of big images quickly.
+    pub fn archival() -> Self {
+        EncodeConfig {
+            decomposition_levels: Some(DEFAULT_LEVELS),
+            precinct_sizes: vec![(256, 256), (256, 256), (128, 128)],
+            progression_order: ProgressionOrder::RPCL,
+            ..EncodeConfig::default()
+        }
+    }
+
+    /// Lossy encoding for the web, whose first layers give a preview of the whole image when
+    /// only the start of the file has been loaded.
+    pub fn web() -> Self {
+        EncodeConfig {
+            transform: Transform::Irreversible,
+            quality_layers: vec![
+                QualityLayer::Ratio(200.0),
+                QualityLayer::Ratio(80.0),
+                QualityLayer::Ratio(40.0),
+                QualityLayer::Ratio(20.0),
+            ],
+            progression_order: ProgressionOrder::LRCP,
+            ..EncodeConfig::default()
+        }
+    }
+}
+
+/// A whole component encoded as region of interest with the Maxshift method (RGN marker), its
+/// coefficients are shifted above those of the other components so they are written first.
+///
+/// The encoder of the linked OpenJPEG (2.3) writes the RGN marker but doesn't shift the
+/// coefficients, while decoders shift the region back down, which would damage it. Encoding
+/// with a region of interest therefore fails with `EncodeError::InvalidConfig` until OpenJPEG
+/// is upgraded. Decoding honours the RGN markers of other encoders.
+///
+/// Only whole components can be a region: OpenJPEG's encoder takes nothing but a component
+/// index and a shift, so rectangular or mask-shaped regions within a component aren't
+/// supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegionOfInterest {
+    pub component: u32,
+    /// The number of bit-planes the region is shifted by, with the precision of the component
+    /// at most 31. A shift larger than the bit-planes of the background separates the region
+    /// completely.
+    pub shift: u8,
+}
+
+/// Constraints on the image and the encoder parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// Textures of the Second Life and OpenSim viewers.
+    SecondLifeTexture,
+    /// DCI 2K digital cinema, signalled in the codestream.
+    ///
+    /// OpenJPEG sets the precincts, the progression order, the tile-parts and the bit rate of
+    /// the profile.
+    Cinema2K,
+    /// DCI 4K digital cinema, signalled in the codestream, see `Profile::Cinema2K`.
+    Cinema4K,
+}
+
+/// The multi-component transform (MCT) applied to the components.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ComponentTransform {
+    /// The colour transform matching the wavelet transform on the first three components of
+    /// sRGB images, unless they are subsampled differently.
+    #[default]
+    Auto,
+    /// Encode every component on its own.
+    Off,
+    /// The colour transform matching the wavelet transform on the first three components,
+    /// which have to be red, green and blue.
+    Rgb,
+    /// A custom decorrelation of all components, written as a JPEG 2000 Part-2 extension.
+    ///
+    /// The transform is done in floating point, so OpenJPEG only allows it together with
+    /// `Transform::Irreversible`.
+    Custom(CustomTransform),
+}
+
+/// A custom multi-component transform for `n` components.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomTransform {
+    /// The `n` by `n` decorrelation matrix in row-major order, row `i` gives the weights of the
+    /// input components for output component `i`. It has to be invertible.
+    pub matrix: Vec<f32>,
+    /// The DC level shift of each component, subtracted before the transform. Empty for no
+    /// shifts.
+    pub dc_shifts: Vec<i32>,
+}
+
+/// The order in which the packets of a tile are written, named after the nesting of layer,
+/// resolution, component and position (precinct).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProgressionOrder {
+    /// Layer first, each layer improves the quality of the whole image.
+    #[default]
+    LRCP,
+    RLCP,
+    /// Resolution first, each resolution doubles the size of the image, for streaming.
+    RPCL,
+    PCRL,
+    CPRL,
+}
+
+impl ProgressionOrder {
+    fn to_i32(self) -> i32 {
+        match self {
+            ProgressionOrder::LRCP => ffi::PROG_ORDER_OPJ_LRCP,
+            ProgressionOrder::RLCP => ffi::PROG_ORDER_OPJ_RLCP,
+            ProgressionOrder::RPCL => ffi::PROG_ORDER_OPJ_RPCL,
+            ProgressionOrder::PCRL => ffi::PROG_ORDER_OPJ_PCRL,
+            ProgressionOrder::CPRL => ffi::PROG_ORDER_OPJ_CPRL,
+        }
+    }
+}
+
+/// A change of the progression order for a range of packets.
+///
+/// The start values are inclusive and the end values exclusive, layers always start at the
+/// first layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProgressionChange {
+    pub order: ProgressionOrder,
+    pub resolution_start: u32,
+    pub resolution_end: u32,
+    pub component_start: u32,
+    pub component_end: u32,
+    pub layer_end: u32,
+}
+
+/// Where to split tiles into tile-parts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TilePartDivision {
+    /// A new tile-part for each resolution.
+    Resolution,
+    /// A new tile-part for each quality layer.
+    Layer,
+    /// A new tile-part for each component.
+    Component,
+}
+
+/// The tile grid of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tiles {
+    pub width: u32,
+    pub height: u32,
+    /// Position of the first tile on the reference grid, it can't lie to the right of or below
+    /// the image offset, and the first tile has to overlap the image.
+    pub origin: (u32, u32),
+}
+
+/// The wavelet transform used for compression.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Transform {
+    /// The 5/3 integer wavelet, lossless if the last quality layer is `QualityLayer::Lossless`.
+    #[default]
+    Reversible,
+    /// The 9/7 floating point wavelet, which is always lossy but compresses better.
+    Irreversible,
+}
+
+/// The target of one quality layer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QualityLayer {
+    /// Compression ratio of the image up to this layer, e.g. `20.0` for a twentieth of the raw
+    /// size (`tcp_rates`).
+    Ratio(f32),
+    /// Peak signal to noise ratio in decibels of the image up to this layer (`tcp_distoratio`).
+    Psnr(f32),
+    /// All the remaining data, only allowed as the last layer.
+    Lossless,
+}
+
+/// Encode planes into a J2K codestream or a JP2 file in memory.
+///
+/// By default the colour channels of sRGB images are decorrelated with the colour transform
+/// matching the wavelet transform of the config, see `EncodeConfig::component_transform`.
+pub fn to_memory(
+    image: &Planes,
+    codec: Codec,
+    config: EncodeConfig,
+    logger: Option<Logger>,
+) -> Result<Vec<u8>, EncodeError> {
+    let keep_sop = config.coding_modes.sop_markers;
+    let mut config = config;
+    if config.index_markers.packet_lengths {
+        config.coding_modes.sop_markers = true;
+    }
+
+    // With boxes of its own OpenJPEG only writes the codestream, which is put into them below.
+    let opj_codec = match config.jp2_boxes {
+        Some(_) if codec != Codec::JP2 => {
+            return Err(EncodeError::InvalidConfig("JP2 boxes need the JP2 codec"))
+        }
+        Some(_) => Codec::J2K,
+        None => codec.clone(),
+    };
+
+    let mut stream = Stream::to_memory()?;
+    encode_to_stream(image, &mut stream, opj_codec, &config, logger)?;
+    let mut output = stream.take_output();
+    if !needs_post_processing(&config) {
+        return Ok(output);
+    }
+
+    // OpenJPEG only writes the first comment.
+    let comments = config.comments.get(1..).unwrap_or(&[]);
+    let edit = |codestream: &mut Vec<u8>| {
+        codestream::insert_index_markers(codestream, config.index_markers, keep_sop)?;
+        codestream::insert_comments(codestream, comments)
+    };
+    if let Some(ref boxes) = config.jp2_boxes {
+        edit(&mut output)?;
+        let components: Vec<_> = image.planes.iter().map(Plane::component).collect();
+        let mut file = jp2::write_header(
+            image.width,
+            image.height,
+            &image.color_space,
+            &components,
+            boxes,
+        )?;
+        jp2::write_box(&mut file, b"jp2c", &output);
+        output = file;
+    } else if codec == Codec::JP2 {
+        codestream::edit_jp2_codestream(&mut output, edit)?;
+    } else {
+        edit(&mut output)?;
+    }
+    Ok(output)
+}
+
+/// Whether the output of OpenJPEG has to be changed, which needs all of it in memory.
+fn needs_post_processing(config: &EncodeConfig) -> bool {
+    config.comments.len() > 1
+        || config.index_markers.tile_part_lengths
+        || config.index_markers.packet_lengths
+        || config.jp2_boxes.is_some()
+}
+
+/// Encode planes into a J2K codestream or a JP2 file, see `to_memory`.
+pub fn to_file<S: Into<String>>(
+    image: &Planes,
+    file_name: S,
+    codec: Codec,
+    config: EncodeConfig,
+    logger: Option<Logger>,
+) -> Result<(), EncodeError> {
+    let file_name = file_name.into();
+    if needs_post_processing(&config) {
+        let output = to_memory(image, codec, config, logger)?;
+        return File::create(&file_name)
+            .and_then(|mut file| file.write_all(&output))
+            .map_err(EncodeError::from);
+    }
+
+    let mut stream = Stream::create_file(file_name)?;
+    encode_to_stream(image, &mut stream, codec, &config, logger)
+}
+
+/// Encode planes into a J2K codestream or a JP2 file written to `writer` from its current
+/// position on, see `to_memory`.
+///
+/// The output is written while encoding, except with more than one comment, index markers or
+/// JP2 boxes, which need the whole output in memory first. The writer is left at the end of the
+/// output.
+pub fn to_writer<W: Write + Seek>(
+    image: &Planes,
+    writer: &mut W,
+    codec: Codec,
+    config: EncodeConfig,
+    logger: Option<Logger>,
+) -> Result<(), EncodeError> {
+    if needs_post_processing(&config) {
+        let output = to_memory(image, codec, config, logger)?;
+        writer.write_all(&output)?;
+        return Ok(());
+    }
+
+    {
+        let mut stream = Stream::to_writer(writer)?;
+        let result = encode_to_stream(image, &mut stream, codec, &config, logger);
+        // OpenJPEG only reports that writing failed, the error of the writer says why.
+        if let Some(err) = stream.take_writer_error() {
+            return Err(EncodeError::Io(err));
+        }
+        result?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Encode an image of the `image` crate, see `to_memory`.
+///
+/// 8 and 16-bit gray and RGB images with or without alpha are encoded as they are, all other
+/// images are converted to 16-bit RGBA first.
+#[cfg(feature = "image")]
+pub fn image_to_memory(
+    image: &DynamicImage,
+    codec: Codec,
+    config: EncodeConfig,
+    logger: Option<Logger>,
+) -> Result<Vec<u8>, EncodeError> {
+    let (width, height) = image.dimensions();
+    let (color_space, channels) = match *image {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLuma16(_) => (ColorSpace::GRAY, 1),
+        DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLumaA16(_) => (ColorSpace::GRAY, 2),
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgb16(_) => (ColorSpace::SRGB, 3),
+        _ => (ColorSpace::SRGB, 4),
+    };
+
+    // Non-integer images are converted, everything else is split into planes directly.
+    let rgba16;
+    let planes_u8;
+    let planes_u16;
+    let planes = match image.as_flat_samples_u8() {
+        Some(flat) => {
+            planes_u8 = deinterleave(flat.samples, channels);
+            planes_u8.iter().map(|plane| Plane::from(&plane[..])).collect()
+        }
+        None => {
+            let samples = match image.as_flat_samples_u16() {
+                Some(flat) => flat.samples,
+                None => {
+                    rgba16 = image.to_rgba16();
+                    rgba16.as_raw()
+                }
+            };
+            planes_u16 = deinterleave(samples, channels);
+            planes_u16.iter().map(|plane| Plane::from(&plane[..])).collect()
+        }
+    };
+
+    let planes = Planes {
+        width,
+        height,
+        color_space: Some(color_space),
+        planes,
+    };
+    to_memory(&planes, codec, config, logger)
+}
+
+fn encode_to_stream(
+    image: &Planes,
+    stream: &mut Stream,
+    codec: Codec,
+    config: &EncodeConfig,
+    logger: Option<Logger>,
+) -> Result<(), EncodeError> {
+    let mut jp2_image = to_opj_image(image, config)?;
+
+    let mut encoder = Encoder::new(codec, logger)?;
+    encoder.setup(config, &mut jp2_image)?;
+    encoder.start_compress(&mut jp2_image, stream)?;
+    encoder.encode(stream)?;
+    encoder.end_compress(stream)?;
+
+    encoder.logger().info(format_args!(
+        "encoded image of {}x{} with {} components",
+        image.width,
+        image.height,
+        image.planes.len()
+    ));
+    Ok(())
+}
+
+fn to_opj_image(image: &Planes, config: &EncodeConfig) -> Result<Image, EncodeError> {
+    for plane in &image.planes {
+        plane.check()?;
+    }
+    let components: Vec<_> = image.planes.iter().map(Plane::component).collect();
+    let mut jp2_image = create_image(
+        image.width,
+        image.height,
+        &image.color_space,
+        &components,
+        config,
+        true,
+    )?;
+
+    for (i, plane) in image.planes.iter().enumerate() {
+        let data = jp2_image.component_data_mut(i);
+        if plane.len() != data.len() {
+            return Err(EncodeError::InvalidInput(
+                "a plane doesn't match the image size and its subsampling",
+            ));
+        }
+        for (sample, value) in data.iter_mut().zip(plane.samples()) {
+            *sample = value;
+        }
+    }
+    Ok(jp2_image)
+}
+
+/// Create an OpenJPEG image for the components, with zeroed samples or without any for
+/// encoding tile by tile.
+pub(crate) fn create_image(
+    width: u32,
+    height: u32,
+    color_space: &Option<ColorSpace>,
+    components: &[Component],
+    config: &EncodeConfig,
+    with_data: bool,
+) -> Result<Image, EncodeError> {
+    if width == 0 || height == 0 {
+        return Err(EncodeError::InvalidInput("the image is empty"));
+    }
+    let (x0, y0) = config.image_offset;
+    if x0.checked_add(width).is_none() || y0.checked_add(height).is_none() {
+        return Err(EncodeError::InvalidConfig("the image doesn't fit on the reference grid"));
+    }
+    if components.is_empty() || components.len() > MAX_COMPONENTS {
+        return Err(EncodeError::InvalidInput("an image has 1 to 16384 planes"));
+    }
+    let has_alpha = match (color_space, components.len()) {
+        (&None, _) | (&Some(ColorSpace::GRAY), 1) | (&Some(ColorSpace::SRGB), 3) => false,
+        (&Some(ColorSpace::GRAY), 2) | (&Some(ColorSpace::SRGB), 4) => true,
+        (&Some(ColorSpace::GRAY), _) | (&Some(ColorSpace::SRGB), _) => {
+            return Err(EncodeError::InvalidInput(
+                "the number of planes doesn't match the color space",
+            ))
+        }
+        _ => {
+            return Err(EncodeError::InvalidInput(
+                "only gray and sRGB images or ones without a color space can be encoded",
+            ))
+        }
+    };
+
+    let mut params = Vec::with_capacity(components.len());
+    for component in components {
+        component.check()?;
+        // Components cover the samples of the reference grid at multiples of their subsampling.
+        let (dx, dy) = component.subsampling;
+        let comp_x0 = x0.div_ceil(dx);
+        let comp_y0 = y0.div_ceil(dy);
+        params.push(ffi::opj_image_cmptparm_t {
+            dx,
+            dy,
+            w: (x0 + width).div_ceil(dx) - comp_x0,
+            h: (y0 + height).div_ceil(dy) - comp_y0,
+            x0: comp_x0,
+            y0: comp_y0,
+            prec: u32::from(component.precision),
+            bpp: u32::from(component.precision),
+            sgnd: component.signed as u32,
+        });
+    }
+    let mut jp2_image = if with_data {
+        Image::new(width, height, &params, color_space.clone())?
+    } else {
+        Image::without_data(width, height, &params, color_space.clone())?
+    };
+    jp2_image.set_offset(x0, y0);
+
+    if has_alpha {
+        let last = components.len() - 1;
+        jp2_image.components_mut()[last].alpha = 1;
+    }
+    Ok(jp2_image)
+}
+
+/// Build the OpenJPEG encoder parameters for the config, without the comment.
+pub(crate) fn encoder_parameters(
+    config: &EncodeConfig,
+    image: &Image,
+) -> Result<ffi::opj_cparameters, EncodeError> {
+    let mut params: ffi::opj_cparameters = unsafe { mem::zeroed() };
+    unsafe { ffi::opj_set_default_encoder_parameters(&mut params) };
+
+    if let Some(profile) = config.profile {
+        check_profile(profile, config, image)?;
+    }
+    if config.comments.iter().any(|comment| codestream::latin1(comment).is_none()) {
+        return Err(EncodeError::InvalidInput("comments have to be Latin-1 text"));
+    }
+
+    let layers = if config.quality_layers.is_empty() {
+        &[QualityLayer::Lossless][..]
+    } else {
+        &config.quality_layers[..]
+    };
+    check_layers(layers, config.transform)?;
+
+    // Lossless layers have a rate or PSNR of 0, which lets OpenJPEG include all remaining data.
+    let by_psnr = layers.iter().any(|layer| matches!(*layer, QualityLayer::Psnr(_)));
+    for (i, layer) in layers.iter().enumerate() {
+        let target = match *layer {
+            QualityLayer::Ratio(target) | QualityLayer::Psnr(target) => target,
+            QualityLayer::Lossless => 0.0,
+        };
+        if by_psnr {
+            params.tcp_distoratio[i] = target;
+        } else {
+            params.tcp_rates[i] = target;
+        }
+    }
+    params.tcp_numlayers = layers.len() as i32;
+    if by_psnr {
+        params.cp_fixed_quality = 1;
+    } else {
+        params.cp_disto_alloc = 1;
+    }
+
+    params.irreversible = match config.transform {
+        Transform::Reversible => 0,
+        Transform::Irreversible => 1,
+    };
+    let num_components = image.components().len();
+    let rgb = image.raw().color_space == ffi::COLOR_SPACE_OPJ_CLRSPC_SRGB;
+    match config.component_transform {
+        ComponentTransform::Auto => params.tcp_mct = (rgb && has_rgb_components(image)) as c_char,
+        ComponentTransform::Off => params.tcp_mct = 0,
+        ComponentTransform::Rgb => {
+            if !has_rgb_components(image) {
+                return Err(EncodeError::InvalidConfig(
+                    "the colour transform needs three components of the same size",
+                ));
+            }
+            params.tcp_mct = 1;
+        }
+        // Set up by the encoder, as OpenJPEG allocates the matrix.
+        ComponentTransform::Custom(ref transform) => {
+            // OpenJPEG would switch to the irreversible wavelet on its own.
+            if config.transform != Transform::Irreversible {
+                return Err(EncodeError::InvalidConfig(
+                    "a custom component transform needs the irreversible wavelet",
+                ));
+            }
+            check_custom_transform(transform, num_components)?;
+            params.tcp_mct = 0;
+        }
+    }
+
+    // The encoder of OpenJPEG 2.3 writes the RGN marker without shifting the coefficients, so
+    // decoders would shift the unshifted region down and damage it.
+    if config.region_of_interest.is_some() {
+        return Err(EncodeError::InvalidConfig(
+            "the linked OpenJPEG can't encode a region of interest",
+        ));
+    }
+
+    let mut min_size = image.width().min(image.height());
+    let mut num_tiles = 1;
+    if let Some(ref tiles) = config.tiles {
+        num_tiles = check_tiles(tiles, image)?;
+        params.tile_size_on = 1;
+        params.cp_tx0 = tiles.origin.0 as i32;
+        params.cp_ty0 = tiles.origin.1 as i32;
+        params.cp_tdx = tiles.width as i32;
+        params.cp_tdy = tiles.height as i32;
+        min_size = min_size.min(tiles.width).min(tiles.height);
+    }
+
+    // OpenJPEG rejects images and tiles that are too small for the number of levels.
+    let levels = match config.decomposition_levels {
+        Some(levels) => {
+            if levels > MAX_LEVELS {
+                return Err(EncodeError::InvalidConfig("too many decomposition levels"));
+            }
+            if u64::from(min_size) < 1 << levels {
+                return Err(EncodeError::InvalidConfig(
+                    "the image or its tiles are too small for the decomposition levels",
+                ));
+            }
+            levels
+        }
+        None => {
+            let mut levels = DEFAULT_LEVELS;
+            while levels > 0 && (1 << levels) > min_size {
+                levels -= 1;
+            }
+            levels
+        }
+    };
+    params.numresolution = levels as i32 + 1;
+
+    let (cblk_width, cblk_height) = config.code_block_size.unwrap_or(DEFAULT_CODE_BLOCK_SIZE);
+    if !is_size_in(cblk_width, 4, 1024) || !is_size_in(cblk_height, 4, 1024) {
+        return Err(EncodeError::InvalidConfig(
+            "code-block sizes have to be powers of two between 4 and 1024",
+        ));
+    }
+    if cblk_width * cblk_height > 4096 {
+        return Err(EncodeError::InvalidConfig(
+            "code-blocks can't have more than 4096 samples",
+        ));
+    }
+    params.cblockw_init = cblk_width as i32;
+    params.cblockh_init = cblk_height as i32;
+
+    if !config.precinct_sizes.is_empty() {
+        if config.precinct_sizes.len() > levels as usize + 1 {
+            return Err(EncodeError::InvalidConfig(
+                "there are more precinct sizes than resolutions",
+            ));
+        }
+        for (i, &(width, height)) in config.precinct_sizes.iter().enumerate() {
+            // Only the lowest resolution can have precincts of a single sample.
+            let min = if i == levels as usize { 1 } else { 2 };
+            if !is_size_in(width, min, 1 << 15) || !is_size_in(height, min, 1 << 15) {
+                return Err(EncodeError::InvalidConfig(
+                    "precinct sizes have to be powers of two between 2 and 2^15",
+                ));
+            }
+            params.prcw_init[i] = width as i32;
+            params.prch_init[i] = height as i32;
+        }
+        params.res_spec = config.precinct_sizes.len() as i32;
+        // Signal user defined precincts in the coding style.
+        params.csty |= 0x01;
+    }
+
+    // These modes terminate more coding passes than OpenJPEG 2.3 sizes the buffers of small
+    // code-blocks for, so it writes past them.
+    let modes = &config.coding_modes;
+    if modes.bypass || modes.termall || modes.segmentation_symbols {
+        return Err(EncodeError::InvalidConfig(
+            "the linked OpenJPEG can't encode with BYPASS, TERMALL or SEGSYM",
+        ));
+    }
+    params.mode = i32::from(modes.code_block_style());
+    if config.coding_modes.sop_markers {
+        params.csty |= 0x02;
+    }
+    if config.coding_modes.eph_markers {
+        params.csty |= 0x04;
+    }
+
+    params.prog_order = config.progression_order.to_i32();
+    if !config.progression_changes.is_empty() {
+        let changes = &config.progression_changes;
+        if changes.len() * num_tiles as usize > MAX_PROGRESSION_CHANGES {
+            return Err(EncodeError::InvalidConfig("too many progression order changes"));
+        }
+        for change in changes {
+            check_progression_change(change, levels + 1, image, layers.len() as u32)?;
+        }
+
+        // OpenJPEG looks up the changes by their (1-based) tile number.
+        let mut i = 0;
+        for tile in 1..=num_tiles as u32 {
+            for change in changes {
+                let poc = &mut params.POC[i];
+                poc.tile = tile;
+                poc.resno0 = change.resolution_start;
+                poc.resno1 = change.resolution_end;
+                poc.compno0 = change.component_start;
+                poc.compno1 = change.component_end;
+                poc.layno1 = change.layer_end;
+                poc.prg1 = change.order.to_i32();
+                i += 1;
+            }
+        }
+        params.numpocs = i as u32;
+    }
+
+    if let Some(division) = config.tile_parts {
+        params.tp_on = 1;
+        params.tp_flag = match division {
+            TilePartDivision::Resolution => b'R',
+            TilePartDivision::Layer => b'L',
+            TilePartDivision::Component => b'C',
+        } as c_char;
+    }
+
+    params.rsiz = match config.profile {
+        Some(Profile::Cinema2K) => ffi::OPJ_PROFILE_CINEMA_2K as u16,
+        Some(Profile::Cinema4K) => ffi::OPJ_PROFILE_CINEMA_4K as u16,
+        Some(Profile::SecondLifeTexture) | None => params.rsiz,
+    };
+
+    Ok(params)
+}
+
+/// Check the constraints of a profile which OpenJPEG doesn't enforce itself.
+fn check_profile(
+    profile: Profile,
+    config: &EncodeConfig,
+    image: &Image,
+) -> Result<(), EncodeError> {
+    let components = image.components();
+    match profile {
+        Profile::SecondLifeTexture => {
+            let (width, height) = (image.width(), image.height());
+            if !is_size_in(width, 1, MAX_TEXTURE_SIZE) || !is_size_in(height, 1, MAX_TEXTURE_SIZE) {
+                return Err(EncodeError::InvalidInput(
+                    "texture sides have to be powers of two up to 2048",
+                ));
+            }
+            if components.len() > 4 {
+                return Err(EncodeError::InvalidInput("textures have at most four components"));
+            }
+            if components.iter().any(|comp| comp.prec != 8 || comp.sgnd != 0) {
+                return Err(EncodeError::InvalidInput("textures have to be 8-bit"));
+            }
+        }
+        Profile::Cinema2K | Profile::Cinema4K => {
+            let (max_width, max_height, max_levels) = if profile == Profile::Cinema2K {
+                (2048, 1080, 5)
+            } else {
+                (4096, 2160, 6)
+            };
+            if components.len() != 3
+                || components.iter().any(|comp| comp.prec != 12 || comp.sgnd != 0)
+                || !components.iter().all(|comp| comp.dx == 1 && comp.dy == 1)
+            {
+                return Err(EncodeError::InvalidInput(
+                    "digital cinema needs three 12-bit components",
+                ));
+            }
+            if image.width() > max_width || image.height() > max_height {
+                return Err(EncodeError::InvalidInput(
+                    "the image is too large for the cinema profile",
+                ));
+            }
+            if !matches!(config.quality_layers[..], [QualityLayer::Ratio(_)])
+                || config.transform != Transform::Irreversible
+                || config.tiles.is_some()
+                || config.image_offset != (0, 0)
+                || config.code_block_size != Some((32, 32))
+                || config.decomposition_levels.is_none_or(|levels| levels > max_levels)
+                || !config.progression_changes.is_empty()
+                || matches!(config.component_transform, ComponentTransform::Custom(_))
+            {
+                return Err(EncodeError::InvalidConfig(
+                    "the config doesn't match the cinema profile, start from its preset",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_progression_change(
+    change: &ProgressionChange,
+    resolutions: u32,
+    image: &Image,
+    layers: u32,
+) -> Result<(), EncodeError> {
+    if change.resolution_start >= change.resolution_end
+        || change.component_start >= change.component_end
+        || change.layer_end == 0
+    {
+        return Err(EncodeError::InvalidConfig(
+            "progression order changes can't be empty",
+        ));
+    }
+    if change.resolution_end > resolutions
+        || change.component_end > image.components().len() as u32
+        || change.layer_end > layers
+    {
+        return Err(EncodeError::InvalidConfig(
+            "a progression order change exceeds the resolutions, components or layers",
+        ));
+    }
+    Ok(())
+}
+
+/// Whether the first three components can be decorrelated with the colour transform.
+fn has_rgb_components(image: &Image) -> bool {
+    match image.components() {
+        [r, g, b, ..] => r.dx == g.dx && r.dx == b.dx && r.dy == g.dy && r.dy == b.dy,
+        _ => false,
+    }
+}
+
+fn check_custom_transform(
+    transform: &CustomTransform,
+    num_components: usize,
+) -> Result<(), EncodeError> {
+    if transform.matrix.len() != num_components * num_components {
+        return Err(EncodeError::InvalidConfig(
+            "the transform matrix doesn't match the number of components",
+        ));
+    }
+    if !transform.matrix.iter().all(|weight| weight.is_finite()) {
+        return Err(EncodeError::InvalidConfig(
+            "the transform matrix has to be finite",
+        ));
+    }
+    if !transform.dc_shifts.is_empty() && transform.dc_shifts.len() != num_components {
+        return Err(EncodeError::InvalidConfig(
+            "the DC shifts don't match the number of components",
+        ));
+    }
+    Ok(())
+}
+
+/// Whether `size` is a power of two between `min` and `max`.
+fn is_size_in(size: u32, min: u32, max: u32) -> bool {
+    size.is_power_of_two() && size >= min && size <= max
+}
+
+/// Check the tile grid against the image and the limits of Part-1, returning the number of
+/// tiles.
+fn check_tiles(tiles: &Tiles, image: &Image) -> Result<u64, EncodeError> {
+    let raw = image.raw();
+    if tiles.width == 0 || tiles.height == 0 {
+        return Err(EncodeError::InvalidConfig("tiles can't be empty"));
+    }
+    let (tx0, ty0) = tiles.origin;
+    if tx0 > raw.x0 || ty0 > raw.y0 {
+        return Err(EncodeError::InvalidConfig(
+            "the tile origin can't lie beyond the image offset",
+        ));
+    }
+    if u64::from(tx0) + u64::from(tiles.width) <= u64::from(raw.x0)
+        || u64::from(ty0) + u64::from(tiles.height) <= u64::from(raw.y0)
+    {
+        return Err(EncodeError::InvalidConfig("the first tile doesn't overlap the image"));
+    }
+
+    let columns = (u64::from(raw.x1) - u64::from(tx0)).div_ceil(u64::from(tiles.width));
+    let rows = (u64::from(raw.y1) - u64::from(ty0)).div_ceil(u64::from(tiles.height));
+    if columns * rows > MAX_TILES {
+        return Err(EncodeError::InvalidConfig("too many tiles"));
+    }
+    Ok(columns * rows)
+}
+
+/// Check that the layers are consistent with each other and with the transform.
+fn check_layers(layers: &[QualityLayer], transform: Transform) -> Result<(), EncodeError> {
+    if layers.len() > MAX_LAYERS {
+        return Err(EncodeError::InvalidConfig("too many quality layers"));
+    }
+    if layers[..layers.len() - 1].contains(&QualityLayer::Lossless) {
+        return Err(EncodeError::InvalidConfig(
+            "only the last quality layer can be lossless",
+        ));
+    }
+    if transform == Transform::Irreversible && layers == [QualityLayer::Lossless] {
+        return Err(EncodeError::InvalidConfig(
+            "the irreversible transform needs lossy quality layers",
+        ));
+    }
+
+    let mut previous = None;
+    for layer in layers {
+        match (previous, *layer) {
+            (_, QualityLayer::Ratio(ratio)) if !(ratio >= 1.0 && ratio.is_finite()) => {
+                return Err(EncodeError::InvalidConfig(
+                    "compression ratios have to be at least 1",
+                ))
+            }
+            (_, QualityLayer::Psnr(psnr)) if !(psnr > 0.0 && psnr.is_finite()) => {
+                return Err(EncodeError::InvalidConfig("PSNR targets have to be positive"))
+            }
+            (Some(QualityLayer::Ratio(_)), QualityLayer::Psnr(_))
+            | (Some(QualityLayer::Psnr(_)), QualityLayer::Ratio(_)) => {
+                return Err(EncodeError::InvalidConfig(
+                    "quality layers can't mix ratios and PSNR targets",
+                ))
+            }
+            (Some(QualityLayer::Ratio(previous)), QualityLayer::Ratio(ratio))
+                if ratio >= previous =>
+            {
+                return Err(EncodeError::InvalidConfig(
+                    "compression ratios have to decrease from layer to layer",
+                ))
+            }
+            (Some(QualityLayer::Psnr(previous)), QualityLayer::Psnr(psnr)) if psnr <= previous => {
+                return Err(EncodeError::InvalidConfig(
+                    "PSNR targets have to increase from layer to layer",
+                ))
+            }
+            _ => {}
+        }
+        previous = Some(*layer);
+    }
+    Ok(())
+}
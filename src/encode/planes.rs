@@ -0,0 +1,179 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use decode::ColorSpace;
+use error::EncodeError;
+
+/// An image made up of one plane per component, each in row-major order.
+#[derive(Clone, Debug)]
+pub struct Planes<'a> {
+    /// The size of the image on the reference grid, subsampled planes are smaller.
+    pub width: u32,
+    pub height: u32,
+    /// Written into the JP2 header, `GRAY` takes 1 or 2 planes and `SRGB` 3 or 4 planes, the
+    /// extra plane being the alpha channel. Without a color space any number of planes up to
+    /// 16384 can be encoded, e.g. the bands of multispectral imagery.
+    pub color_space: Option<ColorSpace>,
+    pub planes: Vec<Plane<'a>>,
+}
+
+/// The samples of one component with their bit depth.
+///
+/// Slices of `u8`, `u16` and `i16` convert into planes using the full width of their type.
+#[derive(Clone, Copy, Debug)]
+pub struct Plane<'a> {
+    pub samples: Samples<'a>,
+    /// Number of bits per sample, at most the width of the sample type and at most 31.
+    pub precision: u8,
+    /// Whether the samples are signed, only possible with signed sample types.
+    pub signed: bool,
+    /// Horizontal and vertical distance of the samples on the reference grid, between 1 and
+    /// 255. The plane has one sample per `dx` by `dy` area of the image.
+    pub subsampling: (u32, u32),
+}
+
+/// The precision, signedness and subsampling of a component, see `Plane`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Component {
+    pub precision: u8,
+    pub signed: bool,
+    pub subsampling: (u32, u32),
+}
+
+impl Component {
+    pub(crate) fn check(&self) -> Result<(), EncodeError> {
+        let (dx, dy) = self.subsampling;
+        if dx == 0 || dx > 255 || dy == 0 || dy > 255 {
+            return Err(EncodeError::InvalidInput(
+                "the subsampling has to be between 1 and 255",
+            ));
+        }
+        if self.precision == 0 || self.precision > 31 {
+            return Err(EncodeError::InvalidInput("the precision has to be between 1 and 31"));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Samples<'a> {
+    U8(&'a [u8]),
+    U16(&'a [u16]),
+    I16(&'a [i16]),
+    I32(&'a [i32]),
+}
+
+impl<'a> Plane<'a> {
+    /// A plane with an explicit precision and signedness, e.g. 12-bit samples stored as `u16`.
+    ///
+    /// The plane isn't subsampled.
+    pub fn new(samples: Samples<'a>, precision: u8, signed: bool) -> Self {
+        Plane {
+            samples,
+            precision,
+            signed,
+            subsampling: (1, 1),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self.samples {
+            Samples::U8(samples) => samples.len(),
+            Samples::U16(samples) => samples.len(),
+            Samples::I16(samples) => samples.len(),
+            Samples::I32(samples) => samples.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The component the plane is a part of.
+    pub fn component(&self) -> Component {
+        Component {
+            precision: self.precision,
+            signed: self.signed,
+            subsampling: self.subsampling,
+        }
+    }
+
+    /// Check the subsampling, the precision and signedness against the sample type and the
+    /// samples against the precision.
+    pub(crate) fn check(&self) -> Result<(), EncodeError> {
+        self.component().check()?;
+        let (max_precision, signed_type) = match self.samples {
+            Samples::U8(_) => (8, false),
+            Samples::U16(_) => (16, false),
+            Samples::I16(_) => (16, true),
+            Samples::I32(_) => (31, true),
+        };
+        if self.precision > max_precision {
+            return Err(EncodeError::InvalidInput(
+                "the precision doesn't fit the sample type",
+            ));
+        }
+        if self.signed && !signed_type {
+            return Err(EncodeError::InvalidInput("unsigned sample types can't be signed"));
+        }
+
+        let (min, max) = if self.signed {
+            (-(1i64 << (self.precision - 1)), (1i64 << (self.precision - 1)) - 1)
+        } else {
+            (0, (1i64 << self.precision) - 1)
+        };
+        let mut samples = self.samples();
+        if samples.any(|sample| i64::from(sample) < min || i64::from(sample) > max) {
+            return Err(EncodeError::InvalidInput("a sample exceeds the precision"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn samples(&self) -> Box<dyn Iterator<Item = i32> + 'a> {
+        match self.samples {
+            Samples::U8(samples) => Box::new(samples.iter().map(|s| i32::from(*s))),
+            Samples::U16(samples) => Box::new(samples.iter().map(|s| i32::from(*s))),
+            Samples::I16(samples) => Box::new(samples.iter().map(|s| i32::from(*s))),
+            Samples::I32(samples) => Box::new(samples.iter().cloned()),
+        }
+    }
+}
+
+impl<'a> From<&'a [u8]> for Plane<'a> {
+    fn from(samples: &'a [u8]) -> Self {
+        Plane::new(Samples::U8(samples), 8, false)
+    }
+}
+
+impl<'a> From<&'a [u16]> for Plane<'a> {
+    fn from(samples: &'a [u16]) -> Self {
+        Plane::new(Samples::U16(samples), 16, false)
+    }
+}
+
+impl<'a> From<&'a [i16]> for Plane<'a> {
+    fn from(samples: &'a [i16]) -> Self {
+        Plane::new(Samples::I16(samples), 16, true)
+    }
+}
+
+/// Split interleaved samples into one plane per channel.
+#[cfg(feature = "image")]
+pub(crate) fn deinterleave<T: Copy>(buf: &[T], channels: usize) -> Vec<Vec<T>> {
+    (0..channels)
+        .map(|c| buf.iter().skip(c).step_by(channels).cloned().collect())
+        .collect()
+}
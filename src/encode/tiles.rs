@@ -0,0 +1,234 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use super::{create_image, Component, EncodeConfig, Plane, Tiles};
+use decode::{Codec, ColorSpace};
+use error::EncodeError;
+use logging::Logger;
+use raw::{Encoder, Image, Stream};
+use std::io::{Seek, Write};
+
+/// The layout of an image encoded tile by tile, without its samples.
+#[derive(Clone, Debug)]
+pub struct TiledImage {
+    /// The size of the image on the reference grid.
+    pub width: u32,
+    pub height: u32,
+    /// See `Planes::color_space`.
+    pub color_space: Option<ColorSpace>,
+    pub components: Vec<Component>,
+}
+
+/// The part of the reference grid covered by a tile, the end coordinates are exclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileRect {
+    /// The index of the tile in raster order.
+    pub index: u32,
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl TileRect {
+    /// The width and height of the plane of a component in this tile.
+    pub fn component_size(&self, component: &Component) -> (u32, u32) {
+        let (dx, dy) = component.subsampling;
+        (
+            self.x1.div_ceil(dx) - self.x0.div_ceil(dx),
+            self.y1.div_ceil(dy) - self.y0.div_ceil(dy),
+        )
+    }
+}
+
+/// Encodes an image one tile at a time, for images too large to be held in memory.
+///
+/// The tiles are taken in raster order: call `write_tile` with the samples of the part of the
+/// image `next_tile` returns until it returns `None`, then `finish`. Only the samples of the
+/// current tile are kept, so reading one row of tiles from the source at a time is enough.
+pub struct TileEncoder<'a> {
+    encoder: Encoder,
+    image: Image,
+    stream: Stream<'a>,
+    components: Vec<Component>,
+    tiles: Tiles,
+    columns: u32,
+    num_tiles: u32,
+    next: u32,
+    /// The samples of the current tile as OpenJPEG expects them, reused for every tile.
+    buffer: Vec<u8>,
+}
+
+impl<'a> TileEncoder<'a> {
+    /// Write the main header to `writer` and prepare for the first tile.
+    ///
+    /// The config has to contain a tile grid, at most one comment, no index markers and no JP2
+    /// boxes can be written.
+    pub fn new<W: Write + Seek>(
+        image: &TiledImage,
+        writer: &'a mut W,
+        codec: Codec,
+        config: EncodeConfig,
+        logger: Option<Logger>,
+    ) -> Result<Self, EncodeError> {
+        let tiles = match config.tiles {
+            Some(tiles) => tiles,
+            None => {
+                return Err(EncodeError::InvalidConfig(
+                    "encoding tile by tile needs a tile grid",
+                ))
+            }
+        };
+        if super::needs_post_processing(&config) {
+            return Err(EncodeError::InvalidConfig(
+                "encoding tile by tile writes no index markers, JP2 boxes or more than one comment",
+            ));
+        }
+
+        let mut jp2_image = create_image(
+            image.width,
+            image.height,
+            &image.color_space,
+            &image.components,
+            &config,
+            false,
+        )?;
+        let mut encoder = Encoder::new(codec, logger)?;
+        // This also checks the tile grid against the image.
+        encoder.setup(&config, &mut jp2_image)?;
+
+        let mut stream = Stream::to_writer(writer)?;
+        let result = encoder.start_compress(&mut jp2_image, &mut stream);
+        check_writer(&mut stream, result)?;
+
+        let raw = jp2_image.raw();
+        let columns = (u64::from(raw.x1) - u64::from(tiles.origin.0))
+            .div_ceil(u64::from(tiles.width)) as u32;
+        let rows = (u64::from(raw.y1) - u64::from(tiles.origin.1))
+            .div_ceil(u64::from(tiles.height)) as u32;
+
+        Ok(TileEncoder {
+            encoder,
+            image: jp2_image,
+            stream,
+            components: image.components.clone(),
+            tiles,
+            columns,
+            num_tiles: columns * rows,
+            next: 0,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// The tile expected by the next call of `write_tile`, `None` once all tiles were written.
+    pub fn next_tile(&self) -> Option<TileRect> {
+        if self.next == self.num_tiles {
+            return None;
+        }
+        let raw = self.image.raw();
+        let column = u64::from(self.next % self.columns);
+        let row = u64::from(self.next / self.columns);
+        let (width, height) = (u64::from(self.tiles.width), u64::from(self.tiles.height));
+        let (tx0, ty0) = (u64::from(self.tiles.origin.0), u64::from(self.tiles.origin.1));
+
+        // The tiles at the borders are cut to the image.
+        Some(TileRect {
+            index: self.next,
+            x0: (tx0 + column * width).max(u64::from(raw.x0)) as u32,
+            y0: (ty0 + row * height).max(u64::from(raw.y0)) as u32,
+            x1: (tx0 + (column + 1) * width).min(u64::from(raw.x1)) as u32,
+            y1: (ty0 + (row + 1) * height).min(u64::from(raw.y1)) as u32,
+        })
+    }
+
+    /// Encode the next tile, with one plane per component of the size given by
+    /// `TileRect::component_size`.
+    pub fn write_tile(&mut self, planes: &[Plane]) -> Result<(), EncodeError> {
+        let tile = match self.next_tile() {
+            Some(tile) => tile,
+            None => return Err(EncodeError::InvalidInput("all tiles were written already")),
+        };
+        if planes.len() != self.components.len() {
+            return Err(EncodeError::InvalidInput(
+                "the number of planes doesn't match the components",
+            ));
+        }
+
+        self.buffer.clear();
+        for (plane, component) in planes.iter().zip(&self.components) {
+            plane.check()?;
+            if plane.component() != *component {
+                return Err(EncodeError::InvalidInput(
+                    "a plane doesn't match its component",
+                ));
+            }
+            let (width, height) = tile.component_size(component);
+            if plane.len() != width as usize * height as usize {
+                return Err(EncodeError::InvalidInput(
+                    "a plane doesn't match the size of the tile",
+                ));
+            }
+
+            match component.precision {
+                1..=8 => self.buffer.extend(plane.samples().map(|sample| sample as u8)),
+                9..=16 => {
+                    for sample in plane.samples() {
+                        self.buffer.extend_from_slice(&(sample as u16).to_ne_bytes());
+                    }
+                }
+                _ => {
+                    for sample in plane.samples() {
+                        self.buffer.extend_from_slice(&sample.to_ne_bytes());
+                    }
+                }
+            }
+        }
+
+        let result = self
+            .encoder
+            .write_tile(tile.index, &mut self.buffer, &mut self.stream);
+        check_writer(&mut self.stream, result)?;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Write the end of the codestream after the last tile and flush the writer.
+    pub fn finish(mut self) -> Result<(), EncodeError> {
+        if self.next != self.num_tiles {
+            return Err(EncodeError::InvalidInput("not all tiles were written"));
+        }
+        let result = self.encoder.end_compress(&mut self.stream);
+        check_writer(&mut self.stream, result)?;
+        self.stream.flush_writer()?;
+
+        self.encoder.logger().info(format_args!(
+            "encoded image of {}x{} with {} components in {} tiles",
+            self.image.width(),
+            self.image.height(),
+            self.components.len(),
+            self.num_tiles
+        ));
+        Ok(())
+    }
+}
+
+/// Prefer the error of the writer, OpenJPEG only reports that writing failed.
+fn check_writer(stream: &mut Stream, result: Result<(), EncodeError>) -> Result<(), EncodeError> {
+    match stream.take_writer_error() {
+        Some(err) => Err(EncodeError::Io(err)),
+        None => result,
+    }
+}
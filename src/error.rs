@@ -14,8 +14,10 @@
 /// You should have received a copy of the GNU General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use decode::support;
 use std::error::Error;
 use std::fmt;
+use std::io;
 
 #[derive(Debug)]
 pub enum DecodeError {
@@ -23,8 +25,20 @@ pub enum DecodeError {
     /// (i.e. if you get this with a published version it's a bug.)
     FfiError(&'static str),
 
-    /// Reading the header failed for some reason.
-    ReadHeader,
+    /// Setting up the decoder with the given parameters failed.
+    SetupDecoder { messages: Vec<String> },
+
+    /// Reading the header failed, `messages` holds the errors reported by OpenJPEG.
+    ReadHeader { messages: Vec<String> },
+
+    /// Decoding the image data failed, e.g. because the input is corrupt.
+    Decode { messages: Vec<String> },
+
+    /// Finishing the decoding failed after the image data was decoded.
+    EndDecompress { messages: Vec<String> },
+
+    /// The input file couldn't be opened.
+    OpenFile(String),
 
     /// There was a null byte in the string.
     NullInString,
@@ -35,6 +49,37 @@ pub enum DecodeError {
 
     UnspecifiedColorSpace,
     UnknownColorSpace,
+
+    /// An indexed decode was requested for an image without a palette.
+    NoPalette,
+
+    /// The palette related boxes of the file are malformed.
+    InvalidPalette(&'static str),
+
+    /// The file doesn't contain a codestream where it should.
+    InvalidCodestream(&'static str),
+
+    /// The boxes of the JP2 file are malformed.
+    InvalidBox(BoxError),
+
+    /// Reading the input failed, or the marker segments of its codestream are malformed.
+    Io(io::Error),
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(e: io::Error) -> Self {
+        // Malformed boxes found while seeking through a reader arrive as I/O errors.
+        match e.get_ref().and_then(|inner| inner.downcast_ref::<BoxError>()) {
+            Some(box_error) => DecodeError::InvalidBox(box_error.clone()),
+            None => DecodeError::Io(e),
+        }
+    }
+}
+
+impl From<BoxError> for DecodeError {
+    fn from(e: BoxError) -> Self {
+        DecodeError::InvalidBox(e)
+    }
 }
 
 impl From<::std::ffi::NulError> for DecodeError {
@@ -43,23 +88,203 @@ impl From<::std::ffi::NulError> for DecodeError {
     }
 }
 
+impl DecodeError {
+    /// The error messages reported by OpenJPEG, if any were captured.
+    pub fn messages(&self) -> &[String] {
+        match *self {
+            DecodeError::SetupDecoder { ref messages }
+            | DecodeError::ReadHeader { ref messages }
+            | DecodeError::Decode { ref messages }
+            | DecodeError::EndDecompress { ref messages } => messages,
+            _ => &[],
+        }
+    }
+
+    /// Whether decoding failed because the codestream ends early.
+    ///
+    /// OpenJPEG fails the decode of most codestreams which end within a tile-part, the report of
+    /// a successful decode tells about the remaining cases, see `DecodeReport::truncated`.
+    pub fn is_truncated(&self) -> bool {
+        self.messages().iter().any(|message| support::is_truncation(message))
+    }
+}
+
 impl Error for DecodeError {
     fn description(&self) -> &str {
         match *self {
             DecodeError::FfiError(e) => e,
-            DecodeError::ReadHeader => "reading the header failed",
+            DecodeError::SetupDecoder { .. } => "setting up the decoder failed",
+            DecodeError::ReadHeader { .. } => "reading the header failed",
+            DecodeError::Decode { .. } => "decoding the image failed",
+            DecodeError::EndDecompress { .. } => "finishing the decoding failed",
+            DecodeError::OpenFile(_) => "opening the input file failed",
             DecodeError::NullInString => "there was a null byte in the string",
             DecodeError::TooManyComponents(_) => {
                 "there were too many components in the supplied file."
             }
             DecodeError::UnspecifiedColorSpace => "Color space was not specified.",
             DecodeError::UnknownColorSpace => "Color space is unknown.",
+            DecodeError::NoPalette => "the image has no palette",
+            DecodeError::InvalidPalette(e) => e,
+            DecodeError::InvalidCodestream(e) => e,
+            DecodeError::InvalidBox(ref e) => e.description(),
+            DecodeError::Io(_) => "reading the input failed",
         }
     }
 }
 
 impl fmt::Display for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.description())
+        match *self {
+            DecodeError::OpenFile(ref file_name) => {
+                write!(f, "{}: {}", self.description(), file_name)
+            }
+            DecodeError::InvalidBox(ref e) => write!(f, "{}", e),
+            _ if !self.messages().is_empty() => {
+                write!(f, "{}: {}", self.description(), self.messages().join("; "))
+            }
+            _ => write!(f, "{}", self.description()),
+        }
+    }
+}
+
+/// A malformed box of a JP2 file, offsets count from the start of the file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BoxError {
+    /// The data ends within the header of a box.
+    TruncatedHeader { offset: usize },
+
+    /// The length of a box is shorter than its header or longer than the data holding it.
+    InvalidLength { offset: usize, box_type: [u8; 4] },
+
+    /// The payload of a box doesn't match its type.
+    InvalidPayload {
+        offset: usize,
+        box_type: [u8; 4],
+        reason: &'static str,
+    },
+}
+
+impl Error for BoxError {
+    fn description(&self) -> &str {
+        match *self {
+            BoxError::TruncatedHeader { .. } => "truncated box header",
+            BoxError::InvalidLength { .. } => "invalid box length",
+            BoxError::InvalidPayload { reason, .. } => reason,
+        }
+    }
+}
+
+impl fmt::Display for BoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BoxError::TruncatedHeader { offset } => {
+                write!(f, "{} at offset {}", self.description(), offset)
+            }
+            BoxError::InvalidLength { offset, box_type }
+            | BoxError::InvalidPayload {
+                offset, box_type, ..
+            } => write!(
+                f,
+                "{} of box '{}' at offset {}",
+                self.description(),
+                String::from_utf8_lossy(&box_type),
+                offset
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    /// Weird FFI errors that should never happen
+    /// (i.e. if you get this with a published version it's a bug.)
+    FfiError(&'static str),
+
+    /// OpenJPEG can only encode J2K codestreams and JP2 files.
+    UnsupportedCodec,
+
+    /// The input image can't be encoded, e.g. because its planes don't match its size.
+    InvalidInput(&'static str),
+
+    /// The encoding options contradict each other.
+    InvalidConfig(&'static str),
+
+    /// Setting up the encoder with the given parameters failed.
+    SetupEncoder { messages: Vec<String> },
+
+    /// Starting the compression failed, `messages` holds the errors reported by OpenJPEG.
+    StartCompress { messages: Vec<String> },
+
+    /// Encoding the image data failed.
+    Encode { messages: Vec<String> },
+
+    /// Finishing the compression failed after the image data was encoded.
+    EndCompress { messages: Vec<String> },
+
+    /// The output file couldn't be created.
+    OpenFile(String),
+
+    /// There was a null byte in the string.
+    NullInString,
+
+    /// Writing the output failed.
+    Io(io::Error),
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(e: io::Error) -> Self {
+        EncodeError::Io(e)
+    }
+}
+
+impl From<::std::ffi::NulError> for EncodeError {
+    fn from(_: ::std::ffi::NulError) -> Self {
+        EncodeError::NullInString
+    }
+}
+
+impl EncodeError {
+    /// The error messages reported by OpenJPEG, if any were captured.
+    pub fn messages(&self) -> &[String] {
+        match *self {
+            EncodeError::SetupEncoder { ref messages }
+            | EncodeError::StartCompress { ref messages }
+            | EncodeError::Encode { ref messages }
+            | EncodeError::EndCompress { ref messages } => messages,
+            _ => &[],
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn description(&self) -> &str {
+        match *self {
+            EncodeError::FfiError(e) => e,
+            EncodeError::UnsupportedCodec => "only J2K and JP2 can be encoded",
+            EncodeError::InvalidInput(e) => e,
+            EncodeError::InvalidConfig(e) => e,
+            EncodeError::SetupEncoder { .. } => "setting up the encoder failed",
+            EncodeError::StartCompress { .. } => "starting the compression failed",
+            EncodeError::Encode { .. } => "encoding the image failed",
+            EncodeError::EndCompress { .. } => "finishing the compression failed",
+            EncodeError::OpenFile(_) => "creating the output file failed",
+            EncodeError::NullInString => "there was a null byte in the string",
+            EncodeError::Io(_) => "writing the output failed",
+        }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::OpenFile(ref file_name) => {
+                write!(f, "{}: {}", self.description(), file_name)
+            }
+            _ if !self.messages().is_empty() => {
+                write!(f, "{}: {}", self.description(), self.messages().join("; "))
+            }
+            _ => write!(f, "{}", self.description()),
+        }
     }
 }
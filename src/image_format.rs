@@ -0,0 +1,196 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use decode::{self, Codec, ColorSpace, DecodeConfig, RgbaPixels};
+use encode::{self, EncodeConfig, Plane, Planes};
+use error::{DecodeError, EncodeError};
+use image::error::{
+    DecodingError, EncodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind,
+};
+use image::hooks::{self, GenericReader};
+use image::{ColorType, ExtendedColorType, ImageDecoder, ImageEncoder, ImageError, ImageResult};
+use std::ffi::OsString;
+use std::io::{Read, Write};
+
+/// File extensions handled by the hooks installed with `register_hooks`.
+pub const EXTENSIONS: [&str; 4] = ["jp2", "j2k", "j2c", "jpx"];
+
+/// An `image::ImageDecoder` for JPEG 2000 files.
+///
+/// The whole image is decoded when the decoder is created. The pixels are returned as RGBA,
+/// with 16-bit samples if any component has more than 8 bits and 8-bit samples otherwise.
+pub struct Jpeg2000Decoder {
+    pixels: RgbaPixels,
+}
+
+impl Jpeg2000Decoder {
+    /// Decode an image, detecting the codec from the data.
+    ///
+    /// Images without a specified color space are treated as sRGB.
+    pub fn new<R: Read>(reader: R) -> Result<Self, DecodeError> {
+        Self::with_config(
+            reader,
+            DecodeConfig {
+                default_colorspace: Some(ColorSpace::SRGB),
+                ..DecodeConfig::default()
+            },
+        )
+    }
+
+    /// Decode an image with the given config, detecting the codec from the data.
+    pub fn with_config<R: Read>(mut reader: R, config: DecodeConfig) -> Result<Self, DecodeError> {
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf)?;
+        let codec = Codec::detect(&buf).unwrap_or(Codec::J2K);
+        let pixels = decode::decode_rgba_pixels(&buf, codec, &config)?;
+
+        Ok(Jpeg2000Decoder { pixels })
+    }
+}
+
+impl ImageDecoder for Jpeg2000Decoder {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.pixels.width, self.pixels.height)
+    }
+
+    fn color_type(&self) -> ColorType {
+        if self.pixels.wide {
+            ColorType::Rgba16
+        } else {
+            ColorType::Rgba8
+        }
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        buf.copy_from_slice(&self.pixels.data);
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+/// An `image::ImageEncoder` writing J2K codestreams or JP2 files.
+///
+/// 8 and 16-bit gray and RGB images with or without alpha are supported, 16-bit samples are
+/// expected in native byte order.
+pub struct Jpeg2000Encoder<W: Write> {
+    writer: W,
+    codec: Codec,
+    config: EncodeConfig,
+}
+
+impl<W: Write> Jpeg2000Encoder<W> {
+    /// Create an encoder writing lossless images.
+    pub fn new(writer: W, codec: Codec) -> Self {
+        Self::with_config(writer, codec, EncodeConfig::default())
+    }
+
+    pub fn with_config(writer: W, codec: Codec, config: EncodeConfig) -> Self {
+        Jpeg2000Encoder {
+            writer,
+            codec,
+            config,
+        }
+    }
+}
+
+impl<W: Write> ImageEncoder for Jpeg2000Encoder<W> {
+    fn write_image(
+        mut self,
+        buf: &[u8],
+        width: u32,
+        height: u32,
+        color_type: ExtendedColorType,
+    ) -> ImageResult<()> {
+        let (color_space, channels, wide) = match color_type {
+            ExtendedColorType::L8 => (ColorSpace::GRAY, 1, false),
+            ExtendedColorType::La8 => (ColorSpace::GRAY, 2, false),
+            ExtendedColorType::Rgb8 => (ColorSpace::SRGB, 3, false),
+            ExtendedColorType::Rgba8 => (ColorSpace::SRGB, 4, false),
+            ExtendedColorType::L16 => (ColorSpace::GRAY, 1, true),
+            ExtendedColorType::La16 => (ColorSpace::GRAY, 2, true),
+            ExtendedColorType::Rgb16 => (ColorSpace::SRGB, 3, true),
+            ExtendedColorType::Rgba16 => (ColorSpace::SRGB, 4, true),
+            _ => {
+                return Err(ImageError::Unsupported(
+                    UnsupportedError::from_format_and_kind(
+                        format_hint(),
+                        UnsupportedErrorKind::Color(color_type),
+                    ),
+                ))
+            }
+        };
+
+        let planes_u8;
+        let planes_u16;
+        let planes = if wide {
+            let samples: Vec<u16> = buf
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect();
+            planes_u16 = encode::deinterleave(&samples, channels);
+            planes_u16.iter().map(|plane| Plane::from(&plane[..])).collect()
+        } else {
+            planes_u8 = encode::deinterleave(buf, channels);
+            planes_u8.iter().map(|plane| Plane::from(&plane[..])).collect()
+        };
+        let planes = Planes {
+            width,
+            height,
+            color_space: Some(color_space),
+            planes,
+        };
+        let data = encode::to_memory(&planes, self.codec, self.config, None)
+            .map_err(to_encoding_error)?;
+        self.writer.write_all(&data)?;
+        Ok(())
+    }
+}
+
+fn format_hint() -> ImageFormatHint {
+    ImageFormatHint::Name("JPEG 2000".to_string())
+}
+
+fn to_image_error(err: DecodeError) -> ImageError {
+    ImageError::Decoding(DecodingError::new(format_hint(), err))
+}
+
+fn to_encoding_error(err: EncodeError) -> ImageError {
+    ImageError::Encoding(EncodingError::new(format_hint(), err))
+}
+
+fn decoding_hook<'a>(reader: GenericReader<'a>) -> ImageResult<Box<dyn ImageDecoder + 'a>> {
+    let decoder = Jpeg2000Decoder::new(reader).map_err(to_image_error)?;
+    Ok(Box::new(decoder))
+}
+
+/// Register the decoder with the `image` crate, so `image::open` handles JPEG 2000 files.
+///
+/// Files are recognised by their extension (see `EXTENSIONS`) and by their signature. Returns
+/// `false` if a hook for one of the extensions was registered before.
+pub fn register_hooks() -> bool {
+    let mut registered = true;
+    for extension in &EXTENSIONS {
+        registered &=
+            hooks::register_decoding_hook(OsString::from(*extension), Box::new(decoding_hook));
+    }
+
+    hooks::register_format_detection_hook(OsString::from("jp2"), &decode::JP2_SIGNATURE, None);
+    hooks::register_format_detection_hook(OsString::from("j2k"), &decode::J2K_SIGNATURE, None);
+    registered
+}
@@ -13,13 +13,38 @@
 ///
 /// You should have received a copy of the GNU General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+#[cfg(feature = "image")]
 extern crate image;
 extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
 extern crate openjpeg2_sys;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "slog")]
 #[macro_use]
 pub extern crate slog;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
+#[cfg(feature = "image")]
 pub use self::image::DynamicImage;
 
+mod buffer;
+pub use buffer::RgbaBuffer;
+
+/// Reading the boxes of JP2 files without decoding them.
+pub mod boxes;
+/// Reading the marker segments of codestreams without decoding them.
+pub mod codestream;
 pub mod decode;
+/// Encoding of images into J2K codestreams and JP2 files.
+pub mod encode;
 pub mod error;
+#[cfg(feature = "image")]
+pub mod image_format;
+pub mod logging;
+/// Safe wrappers around the OpenJPEG handles, for callers that need more control than the
+/// functions in `decode` offer.
+pub mod raw;
+pub use logging::Logger;
@@ -0,0 +1,185 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use decode::{Codec, DecodeReport};
+use std::fmt;
+
+/// The backend receiving the messages of OpenJPEG and of the decoder.
+///
+/// Each backend is only available with the cargo feature of the same name enabled.
+#[derive(Clone, Default)]
+pub enum Logger {
+    /// Drop all messages.
+    #[default]
+    Discard,
+    /// Log to a `slog` logger, each decode and encode gets a child logger carrying the codec.
+    #[cfg(feature = "slog")]
+    Slog(::slog::Logger),
+    /// Log through the `log` crate's global logger.
+    #[cfg(feature = "log")]
+    Log,
+    /// Emit `tracing` events inside one span per decode or encode, the span records the codec,
+    /// and for decodes the dimensions and the timings.
+    #[cfg(feature = "tracing")]
+    Tracing,
+}
+
+#[cfg(feature = "slog")]
+impl From<::slog::Logger> for Logger {
+    fn from(logger: ::slog::Logger) -> Self {
+        Logger::Slog(logger)
+    }
+}
+
+#[cfg_attr(not(any(feature = "slog", feature = "log", feature = "tracing")),
+           allow(unused_variables))]
+impl Logger {
+    /// Attach the index of an item of a batch decode.
+    #[cfg(feature = "rayon")]
+    #[cfg_attr(not(feature = "slog"), allow(unused_variables))]
+    pub(crate) fn with_batch_index(&self, index: usize) -> Logger {
+        match *self {
+            #[cfg(feature = "slog")]
+            Logger::Slog(ref logger) => Logger::Slog(logger.new(o!("batch_index" => index))),
+            _ => self.clone(),
+        }
+    }
+
+    /// Create the logger used for the decoding of a single image.
+    pub(crate) fn for_decode(&self, codec: &Codec) -> OpjLogger {
+        match *self {
+            Logger::Discard => OpjLogger::Discard,
+            #[cfg(feature = "slog")]
+            Logger::Slog(ref logger) => OpjLogger::Slog(logger.new(o!(
+                "function" => "decode jpeg2000 stream",
+                "codec" => format!("{:?}", codec)
+            ))),
+            #[cfg(feature = "log")]
+            Logger::Log => OpjLogger::Log(codec.clone()),
+            #[cfg(feature = "tracing")]
+            Logger::Tracing => OpjLogger::Tracing(::tracing::info_span!(
+                "jpeg2000_decode",
+                codec = ?codec,
+                width = ::tracing::field::Empty,
+                height = ::tracing::field::Empty,
+                header_time_us = ::tracing::field::Empty,
+                decode_time_us = ::tracing::field::Empty
+            )),
+        }
+    }
+
+    /// Create the logger used for the encoding of a single image.
+    pub(crate) fn for_encode(&self, codec: &Codec) -> OpjLogger {
+        match *self {
+            Logger::Discard => OpjLogger::Discard,
+            #[cfg(feature = "slog")]
+            Logger::Slog(ref logger) => OpjLogger::Slog(logger.new(o!(
+                "function" => "encode jpeg2000 stream",
+                "codec" => format!("{:?}", codec)
+            ))),
+            #[cfg(feature = "log")]
+            Logger::Log => OpjLogger::Log(codec.clone()),
+            #[cfg(feature = "tracing")]
+            Logger::Tracing => OpjLogger::Tracing(::tracing::info_span!(
+                "jpeg2000_encode",
+                codec = ?codec
+            )),
+        }
+    }
+}
+
+/// The logger of a single decode or encode, receiving the messages of OpenJPEG and of this
+/// crate, see `Logger::for_decode` and `Logger::for_encode`.
+#[derive(Clone)]
+pub(crate) enum OpjLogger {
+    Discard,
+    #[cfg(feature = "slog")]
+    Slog(::slog::Logger),
+    #[cfg(feature = "log")]
+    Log(Codec),
+    #[cfg(feature = "tracing")]
+    Tracing(::tracing::Span),
+}
+
+#[cfg_attr(not(any(feature = "slog", feature = "log", feature = "tracing")),
+           allow(unused_variables))]
+impl OpjLogger {
+    pub fn info(&self, args: fmt::Arguments) {
+        match *self {
+            OpjLogger::Discard => {}
+            #[cfg(feature = "slog")]
+            OpjLogger::Slog(ref logger) => info!(logger, "{}", args),
+            #[cfg(feature = "log")]
+            OpjLogger::Log(_) => ::log::info!("{}", args),
+            #[cfg(feature = "tracing")]
+            OpjLogger::Tracing(ref span) => span.in_scope(|| ::tracing::info!("{}", args)),
+        }
+    }
+
+    pub fn warn(&self, args: fmt::Arguments) {
+        match *self {
+            OpjLogger::Discard => {}
+            #[cfg(feature = "slog")]
+            OpjLogger::Slog(ref logger) => warn!(logger, "{}", args),
+            #[cfg(feature = "log")]
+            OpjLogger::Log(_) => ::log::warn!("{}", args),
+            #[cfg(feature = "tracing")]
+            OpjLogger::Tracing(ref span) => span.in_scope(|| ::tracing::warn!("{}", args)),
+        }
+    }
+
+    pub fn error(&self, args: fmt::Arguments) {
+        match *self {
+            OpjLogger::Discard => {}
+            #[cfg(feature = "slog")]
+            OpjLogger::Slog(ref logger) => error!(logger, "{}", args),
+            #[cfg(feature = "log")]
+            OpjLogger::Log(_) => ::log::error!("{}", args),
+            #[cfg(feature = "tracing")]
+            OpjLogger::Tracing(ref span) => span.in_scope(|| ::tracing::error!("{}", args)),
+        }
+    }
+
+    /// Record the dimensions and timings of a successful decode.
+    pub fn finished(&self, width: u32, height: u32, report: &DecodeReport) {
+        match *self {
+            OpjLogger::Discard => {}
+            #[cfg(feature = "slog")]
+            OpjLogger::Slog(ref logger) => info!(logger, "decoded image";
+                "width" => width,
+                "height" => height,
+                "header_time" => format!("{:?}", report.header_time),
+                "decode_time" => format!("{:?}", report.decode_time)
+            ),
+            #[cfg(feature = "log")]
+            OpjLogger::Log(ref codec) => ::log::info!(
+                "decoded {:?} image of {}x{} (header: {:?}, decode: {:?})",
+                codec,
+                width,
+                height,
+                report.header_time,
+                report.decode_time
+            ),
+            #[cfg(feature = "tracing")]
+            OpjLogger::Tracing(ref span) => {
+                span.record("width", &width);
+                span.record("height", &height);
+                span.record("header_time_us", &(report.header_time.as_micros() as u64));
+                span.record("decode_time_us", &(report.decode_time.as_micros() as u64));
+            }
+        }
+    }
+}
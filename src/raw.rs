@@ -0,0 +1,605 @@
+/// jpeg2000: Rust bindings to the OpenJPEG library.
+/// Copyright (C) 2017 Leonardo Schwarz <mail@leoschwarz.com>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU General Public License as published by
+/// the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU General Public License for more details.
+///
+/// You should have received a copy of the GNU General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use codestream;
+use decode::support::{self, LogHandlerData, NdUserdata};
+use decode::{self, Codec, ColorSpace, DecodeConfig};
+use encode::{self, ComponentTransform, EncodeConfig};
+use error::{DecodeError, EncodeError};
+use logging::{Logger, OpjLogger};
+use openjpeg2_sys as ffi;
+use std::ffi::CString;
+use std::io::{self, Seek, Write};
+use std::os::raw::{c_char, c_void};
+use std::ptr::null_mut;
+use std::slice;
+
+/// An OpenJPEG input or output stream, destroyed when dropped.
+pub struct Stream<'a> {
+    ptr: *mut ffi::opj_stream_t,
+    /// The state of the callbacks of memory streams, it has to stay at the same address for as
+    /// long as the stream exists.
+    _userdata: Option<Box<NdUserdata<'a>>>,
+}
+
+impl<'a> Stream<'a> {
+    /// Create a stream reading from a buffer.
+    pub fn from_memory(buf: &'a [u8]) -> Result<Self, DecodeError> {
+        let mut userdata = Box::new(NdUserdata::new_input(buf));
+
+        unsafe {
+            let ptr = ffi::opj_stream_default_create(1);
+            if ptr.is_null() {
+                return Err(DecodeError::FfiError("Stream instantiation failed."));
+            }
+            ffi::opj_stream_set_read_function(ptr, Some(support::nd_opj_stream_read_fn));
+            ffi::opj_stream_set_write_function(ptr, Some(support::nd_opj_stream_write_fn));
+            ffi::opj_stream_set_skip_function(ptr, Some(support::nd_opj_stream_skip_fn));
+            ffi::opj_stream_set_seek_function(ptr, Some(support::nd_opj_stream_seek_fn));
+
+            let userdata_ptr: *mut NdUserdata = &mut *userdata;
+            ffi::opj_stream_set_user_data_length(ptr, buf.len() as u64);
+            ffi::opj_stream_set_user_data(ptr, userdata_ptr as *mut c_void, None);
+
+            Ok(Stream {
+                ptr,
+                _userdata: Some(userdata),
+            })
+        }
+    }
+}
+
+impl Stream<'static> {
+    /// Create a stream reading from a file.
+    pub fn from_file<S: Into<String>>(file_name: S) -> Result<Self, DecodeError> {
+        let file_name = file_name.into();
+        let path = CString::new(file_name.clone())?;
+        let ptr = unsafe { ffi::opj_stream_create_default_file_stream(path.as_ptr(), 1) };
+        if ptr.is_null() {
+            return Err(DecodeError::OpenFile(file_name));
+        }
+
+        Ok(Stream {
+            ptr,
+            _userdata: None,
+        })
+    }
+}
+
+impl Stream<'static> {
+    /// Create a stream writing into memory, see `take_output`.
+    pub fn to_memory() -> Result<Self, EncodeError> {
+        let mut userdata = Box::new(NdUserdata::new_output());
+
+        unsafe {
+            let ptr = ffi::opj_stream_default_create(0);
+            if ptr.is_null() {
+                return Err(EncodeError::FfiError("Stream instantiation failed."));
+            }
+            ffi::opj_stream_set_read_function(ptr, Some(support::nd_opj_stream_read_fn));
+            ffi::opj_stream_set_write_function(ptr, Some(support::nd_opj_stream_write_fn));
+            ffi::opj_stream_set_skip_function(ptr, Some(support::nd_opj_stream_skip_fn));
+            ffi::opj_stream_set_seek_function(ptr, Some(support::nd_opj_stream_seek_fn));
+
+            let userdata_ptr: *mut NdUserdata = &mut *userdata;
+            ffi::opj_stream_set_user_data(ptr, userdata_ptr as *mut c_void, None);
+
+            Ok(Stream {
+                ptr,
+                _userdata: Some(userdata),
+            })
+        }
+    }
+
+    /// Create a stream writing to a file, replacing an existing file.
+    pub fn create_file<S: Into<String>>(file_name: S) -> Result<Self, EncodeError> {
+        let file_name = file_name.into();
+        let path = CString::new(file_name.clone())?;
+        let ptr = unsafe { ffi::opj_stream_create_default_file_stream(path.as_ptr(), 0) };
+        if ptr.is_null() {
+            return Err(EncodeError::OpenFile(file_name));
+        }
+
+        Ok(Stream {
+            ptr,
+            _userdata: None,
+        })
+    }
+
+    /// Take the bytes written to a stream created with `to_memory`.
+    ///
+    /// The output is only complete once the compression was ended, other streams return nothing.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        match self._userdata {
+            Some(ref mut userdata) => userdata.take_output(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl<'a> Stream<'a> {
+    /// Create a stream writing to `writer` from its current position on.
+    ///
+    /// The writer has to be seekable, since the JP2 writer goes back to fill in the length of
+    /// the codestream box. Errors of the writer are kept, see `take_writer_error`.
+    pub fn to_writer<W: Write + Seek>(writer: &'a mut W) -> Result<Self, EncodeError> {
+        let mut userdata = Box::new(NdUserdata::new_sink(writer)?);
+
+        unsafe {
+            let ptr = ffi::opj_stream_default_create(0);
+            if ptr.is_null() {
+                return Err(EncodeError::FfiError("Stream instantiation failed."));
+            }
+            ffi::opj_stream_set_write_function(ptr, Some(support::nd_opj_stream_write_fn));
+            ffi::opj_stream_set_skip_function(ptr, Some(support::nd_opj_stream_skip_fn));
+            ffi::opj_stream_set_seek_function(ptr, Some(support::nd_opj_stream_seek_fn));
+
+            let userdata_ptr: *mut NdUserdata = &mut *userdata;
+            ffi::opj_stream_set_user_data(ptr, userdata_ptr as *mut c_void, None);
+
+            Ok(Stream {
+                ptr,
+                _userdata: Some(userdata),
+            })
+        }
+    }
+
+    /// Flush the writer of a stream created with `to_writer`.
+    pub fn flush_writer(&mut self) -> io::Result<()> {
+        match self._userdata {
+            Some(ref mut userdata) => userdata.flush_sink(),
+            None => Ok(()),
+        }
+    }
+
+    /// Take the first error the writer of a stream created with `to_writer` returned.
+    pub fn take_writer_error(&mut self) -> Option<io::Error> {
+        match self._userdata {
+            Some(ref mut userdata) => userdata.take_sink_error(),
+            None => None,
+        }
+    }
+}
+
+impl<'a> Drop for Stream<'a> {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_stream_destroy(self.ptr) }
+    }
+}
+
+/// An OpenJPEG decompression codec, destroyed when dropped.
+///
+/// The messages OpenJPEG reports while using the decoder are passed on to the logger and collected,
+/// so they can be returned with the errors.
+pub struct Decoder {
+    ptr: *mut ffi::opj_codec_t,
+    /// Passed to the message handlers of the codec, it has to stay at the same address for as
+    /// long as the codec exists.
+    handler_data: Box<LogHandlerData>,
+}
+
+impl Decoder {
+    /// Create a decoder for the given codec.
+    pub fn new(codec: Codec, logger: Option<Logger>) -> Result<Self, DecodeError> {
+        let logger = logger.unwrap_or_default().for_decode(&codec);
+
+        let ptr = unsafe { ffi::opj_create_decompress(codec.to_i32()) };
+        if ptr.is_null() {
+            return Err(DecodeError::FfiError("Codec instantiation failed."));
+        }
+        // From here on the codec is destroyed by `drop`, even if setting the handlers fails.
+        let mut decoder = Decoder {
+            ptr,
+            handler_data: Box::new(LogHandlerData::new(logger)),
+        };
+
+        if !unsafe { set_handlers(ptr, &mut decoder.handler_data) } {
+            return Err(DecodeError::FfiError("Setting the message handlers failed."));
+        }
+        Ok(decoder)
+    }
+
+    /// Set up the decoder for the given config.
+    pub fn setup(&mut self, config: &DecodeConfig) -> Result<(), DecodeError> {
+        let params = decode::decoder_parameters(decode::default_decoder_parameters(), config, 0);
+        self.setup_with_parameters(params)
+    }
+
+    pub(crate) fn setup_with_parameters(
+        &mut self,
+        mut params: ffi::opj_dparameters,
+    ) -> Result<(), DecodeError> {
+        if unsafe { ffi::opj_setup_decoder(self.ptr, &mut params) } != 1 {
+            return Err(DecodeError::SetupDecoder {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Read the main header, returning an image without any pixel data yet.
+    pub fn read_header(&mut self, stream: &mut Stream) -> Result<Image, DecodeError> {
+        let mut ptr: *mut ffi::opj_image = null_mut();
+        let ok = unsafe { ffi::opj_read_header(stream.ptr, self.ptr, &mut ptr) };
+        // OpenJPEG might have allocated the image even if reading the header failed.
+        let image = Image { ptr };
+        if ok != 1 || image.ptr.is_null() {
+            return Err(DecodeError::ReadHeader {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(image)
+    }
+
+    /// The number of quality layers of the codestream, available once the header was read.
+    pub fn num_layers(&self) -> Option<u32> {
+        unsafe {
+            let mut cstr_info = ffi::opj_get_cstr_info(self.ptr);
+            if cstr_info.is_null() {
+                return None;
+            }
+            let num_layers = (*cstr_info).m_default_tile_info.numlayers;
+            ffi::opj_destroy_cstr_info(&mut cstr_info);
+            Some(num_layers)
+        }
+    }
+
+    /// Decode the image data into the image returned by `read_header`.
+    pub fn decode(&mut self, stream: &mut Stream, image: &mut Image) -> Result<(), DecodeError> {
+        if unsafe { ffi::opj_decode(self.ptr, stream.ptr, image.ptr) } != 1 {
+            return Err(DecodeError::Decode {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Read the rest of the codestream after decoding.
+    pub fn end_decompress(&mut self, stream: &mut Stream) -> Result<(), DecodeError> {
+        if unsafe { ffi::opj_end_decompress(self.ptr, stream.ptr) } != 1 {
+            return Err(DecodeError::EndDecompress {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The warnings and info messages reported so far.
+    pub(crate) fn handler_data(&mut self) -> &mut LogHandlerData {
+        &mut self.handler_data
+    }
+
+    pub(crate) fn logger(&self) -> &OpjLogger {
+        self.handler_data.logger()
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_destroy_codec(self.ptr) }
+    }
+}
+
+/// Route the messages of a codec to the handler data.
+unsafe fn set_handlers(codec: *mut ffi::opj_codec_t, data: &mut LogHandlerData) -> bool {
+    let data_ptr: *mut LogHandlerData = data;
+    let data_ptr = data_ptr as *mut c_void;
+    ffi::opj_set_info_handler(codec, Some(support::info_handler), data_ptr) == 1
+        && ffi::opj_set_warning_handler(codec, Some(support::warning_handler), data_ptr) == 1
+        && ffi::opj_set_error_handler(codec, Some(support::error_handler), data_ptr) == 1
+}
+
+/// An OpenJPEG compression codec, destroyed when dropped.
+///
+/// Like `Decoder`, it collects the messages OpenJPEG reports so they can be returned with the
+/// errors.
+pub struct Encoder {
+    ptr: *mut ffi::opj_codec_t,
+    /// Passed to the message handlers of the codec, it has to stay at the same address for as
+    /// long as the codec exists.
+    handler_data: Box<LogHandlerData>,
+}
+
+impl Encoder {
+    /// Create an encoder for the given codec, only J2K and JP2 are supported.
+    pub fn new(codec: Codec, logger: Option<Logger>) -> Result<Self, EncodeError> {
+        if codec != Codec::J2K && codec != Codec::JP2 {
+            return Err(EncodeError::UnsupportedCodec);
+        }
+        let logger = logger.unwrap_or_default().for_encode(&codec);
+
+        let ptr = unsafe { ffi::opj_create_compress(codec.to_i32()) };
+        if ptr.is_null() {
+            return Err(EncodeError::FfiError("Codec instantiation failed."));
+        }
+        // From here on the codec is destroyed by `drop`, even if setting the handlers fails.
+        let mut encoder = Encoder {
+            ptr,
+            handler_data: Box::new(LogHandlerData::new(logger)),
+        };
+
+        if !unsafe { set_handlers(ptr, &mut encoder.handler_data) } {
+            return Err(EncodeError::FfiError("Setting the message handlers failed."));
+        }
+        Ok(encoder)
+    }
+
+    /// Set up the encoder for the given config and image.
+    ///
+    /// Only the first of the comments is written, the functions of `encode` insert the others
+    /// into the finished output.
+    pub fn setup(&mut self, config: &EncodeConfig, image: &mut Image) -> Result<(), EncodeError> {
+        let mut params = encode::encoder_parameters(config, image)?;
+
+        // OpenJPEG copies the comment, so it only has to outlive the setup. The comments have
+        // been checked to be Latin-1 by `encoder_parameters`.
+        let comment = match config.comments.first().and_then(|c| codestream::latin1(c)) {
+            Some(comment) => Some(CString::new(comment)?),
+            None => None,
+        };
+        if let Some(ref comment) = comment {
+            params.cp_comment = comment.as_ptr() as *mut c_char;
+        }
+
+        // The config has been checked against the image by `encoder_parameters`.
+        if let ComponentTransform::Custom(ref transform) = config.component_transform {
+            let mut matrix = transform.matrix.clone();
+            let mut dc_shifts = transform.dc_shifts.clone();
+            dc_shifts.resize(image.components().len(), 0);
+            let ok = unsafe {
+                ffi::opj_set_MCT(
+                    &mut params,
+                    matrix.as_mut_ptr(),
+                    dc_shifts.as_mut_ptr(),
+                    dc_shifts.len() as u32,
+                )
+            };
+            if ok != 1 {
+                return Err(EncodeError::FfiError("Setting the component transform failed."));
+            }
+        }
+
+        let ok = unsafe { ffi::opj_setup_encoder(self.ptr, &mut params, image.ptr) };
+        // OpenJPEG frees the matrix set by `opj_set_MCT` and clears the pointer once the setup
+        // succeeded, if it fails early the matrix is left to us.
+        if !params.mct_data.is_null() {
+            unsafe { libc::free(params.mct_data) };
+        }
+        if ok != 1 {
+            return Err(EncodeError::SetupEncoder {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the main header to the stream.
+    pub fn start_compress(
+        &mut self,
+        image: &mut Image,
+        stream: &mut Stream,
+    ) -> Result<(), EncodeError> {
+        if unsafe { ffi::opj_start_compress(self.ptr, image.ptr, stream.ptr) } != 1 {
+            return Err(EncodeError::StartCompress {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Encode the image data passed to `start_compress`.
+    pub fn encode(&mut self, stream: &mut Stream) -> Result<(), EncodeError> {
+        if unsafe { ffi::opj_encode(self.ptr, stream.ptr) } != 1 {
+            return Err(EncodeError::Encode {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Encode the tile with the given index, instead of calling `encode`.
+    ///
+    /// Tiles have to be written in raster order after `start_compress`. The data holds the
+    /// samples of each component in turn, row by row, using 1, 2 or 4 bytes in native byte order
+    /// for precisions up to 8, 16 and 32 bits.
+    pub fn write_tile(
+        &mut self,
+        index: u32,
+        data: &mut [u8],
+        stream: &mut Stream,
+    ) -> Result<(), EncodeError> {
+        if data.len() > u32::MAX as usize {
+            return Err(EncodeError::InvalidInput("the tile is too large"));
+        }
+        let ok = unsafe {
+            ffi::opj_write_tile(
+                self.ptr,
+                index,
+                data.as_mut_ptr(),
+                data.len() as u32,
+                stream.ptr,
+            )
+        };
+        if ok != 1 {
+            return Err(EncodeError::Encode {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the end of the codestream and flush the stream.
+    pub fn end_compress(&mut self, stream: &mut Stream) -> Result<(), EncodeError> {
+        if unsafe { ffi::opj_end_compress(self.ptr, stream.ptr) } != 1 {
+            return Err(EncodeError::EndCompress {
+                messages: self.handler_data.take_errors(),
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn logger(&self) -> &OpjLogger {
+        self.handler_data.logger()
+    }
+}
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_destroy_codec(self.ptr) }
+    }
+}
+
+/// An image allocated by OpenJPEG, destroyed when dropped.
+pub struct Image {
+    ptr: *mut ffi::opj_image,
+}
+
+impl Image {
+    /// Allocate an image with zeroed components, covering `width` times `height` pixels of the
+    /// reference grid.
+    pub fn new(
+        width: u32,
+        height: u32,
+        components: &[ffi::opj_image_cmptparm_t],
+        color_space: Option<ColorSpace>,
+    ) -> Result<Self, EncodeError> {
+        let mut components = components.to_vec();
+        let ptr = unsafe {
+            ffi::opj_image_create(
+                components.len() as u32,
+                components.as_mut_ptr(),
+                color_space.map_or(ffi::COLOR_SPACE_OPJ_CLRSPC_UNSPECIFIED, |cs| cs.to_i32()),
+            )
+        };
+        Image::from_new(ptr, width, height)
+    }
+
+    /// Allocate an image without any samples, for encoding it tile by tile with
+    /// `Encoder::write_tile`.
+    pub fn without_data(
+        width: u32,
+        height: u32,
+        components: &[ffi::opj_image_cmptparm_t],
+        color_space: Option<ColorSpace>,
+    ) -> Result<Self, EncodeError> {
+        let mut components = components.to_vec();
+        let ptr = unsafe {
+            ffi::opj_image_tile_create(
+                components.len() as u32,
+                components.as_mut_ptr(),
+                color_space.map_or(ffi::COLOR_SPACE_OPJ_CLRSPC_UNSPECIFIED, |cs| cs.to_i32()),
+            )
+        };
+        Image::from_new(ptr, width, height)
+    }
+
+    fn from_new(ptr: *mut ffi::opj_image, width: u32, height: u32) -> Result<Self, EncodeError> {
+        if ptr.is_null() {
+            return Err(EncodeError::FfiError("Image allocation failed."));
+        }
+
+        let mut image = Image { ptr };
+        let raw = image.raw_mut();
+        raw.x0 = 0;
+        raw.y0 = 0;
+        raw.x1 = width;
+        raw.y1 = height;
+        Ok(image)
+    }
+
+    /// The underlying OpenJPEG image.
+    pub(crate) fn raw(&self) -> &ffi::opj_image {
+        unsafe { &*self.ptr }
+    }
+
+    /// Move the image to the given position on the reference grid, keeping its size.
+    ///
+    /// The sizes of subsampled components depend on the position and aren't changed, so the
+    /// components have to be created for it.
+    ///
+    /// Panics if the image would no longer fit on the reference grid.
+    pub(crate) fn set_offset(&mut self, x0: u32, y0: u32) {
+        let width = self.width();
+        let height = self.height();
+        let raw = self.raw_mut();
+        raw.x0 = x0;
+        raw.y0 = y0;
+        raw.x1 = x0.checked_add(width).expect("image offset too large");
+        raw.y1 = y0.checked_add(height).expect("image offset too large");
+        for comp in self.components_mut() {
+            comp.x0 = x0.div_ceil(comp.dx);
+            comp.y0 = y0.div_ceil(comp.dy);
+        }
+    }
+
+    pub(crate) fn raw_mut(&mut self) -> &mut ffi::opj_image {
+        unsafe { &mut *self.ptr }
+    }
+
+    /// The width of the image on the reference grid.
+    pub fn width(&self) -> u32 {
+        self.raw().x1 - self.raw().x0
+    }
+
+    /// The height of the image on the reference grid.
+    pub fn height(&self) -> u32 {
+        self.raw().y1 - self.raw().y0
+    }
+
+    pub fn components(&self) -> &[ffi::opj_image_comp] {
+        let raw = self.raw();
+        if raw.comps.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(raw.comps, raw.numcomps as usize) }
+    }
+
+    /// Changing the size of a component would let `component_data` read past its samples, so
+    /// this isn't public.
+    pub(crate) fn components_mut(&mut self) -> &mut [ffi::opj_image_comp] {
+        let raw = self.raw_mut();
+        if raw.comps.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(raw.comps, raw.numcomps as usize) }
+    }
+
+    /// The samples of a component in row-major order, empty before the image was decoded.
+    ///
+    /// Panics if there is no component with that index.
+    pub fn component_data(&self, index: usize) -> &[i32] {
+        let comp = &self.components()[index];
+        if comp.data.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(comp.data, comp.w as usize * comp.h as usize) }
+    }
+
+    /// Mutable access to the samples of a component, see `component_data`.
+    pub(crate) fn component_data_mut(&mut self, index: usize) -> &mut [i32] {
+        let comp = &self.components_mut()[index];
+        if comp.data.is_null() {
+            return &mut [];
+        }
+        unsafe { slice::from_raw_parts_mut(comp.data, comp.w as usize * comp.h as usize) }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe { ffi::opj_image_destroy(self.ptr) }
+    }
+}
@@ -0,0 +1,64 @@
+#![cfg(feature = "rayon")]
+
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, batch, Codec, ColorSpace, DecodeConfig};
+use std::fs;
+
+const IMAGES: [(&str, Codec); 2] = [
+    (
+        concat!(env!("CARGO_MANIFEST_DIR"), "/examples/images/rust_logo.jp2"),
+        Codec::JP2,
+    ),
+    // A codestream, despite its name.
+    (
+        concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/examples/images/opensim_texture.jp2"
+        ),
+        Codec::J2K,
+    ),
+];
+
+fn config() -> DecodeConfig {
+    DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    }
+}
+
+#[test]
+fn batch_matches_single_decodes() {
+    for (file_name, codec) in IMAGES.iter().cloned() {
+        let (expected, expected_report) =
+            decode::from_file_with_report(file_name, codec.clone(), config(), None).unwrap();
+
+        // More images than threads, so threads decode several of them.
+        let file_names = vec![file_name; 8];
+        let results = batch::from_files_with_report(&file_names, codec.clone(), &config(), None);
+        assert_eq!(results.len(), file_names.len());
+        for result in results {
+            let (image, report) = result.unwrap();
+            assert!(image == expected, "{} differs", file_name);
+            assert_eq!(report.layers, expected_report.layers);
+            assert_eq!(report.modes, expected_report.modes);
+            assert_eq!(report.comments, expected_report.comments);
+        }
+
+        let buffers = vec![fs::read(file_name).unwrap(); 8];
+        for result in batch::from_memory(&buffers, codec.clone(), &config(), None) {
+            assert!(result.unwrap() == expected, "{} differs", file_name);
+        }
+    }
+}
+
+#[test]
+fn broken_image_fails_alone() {
+    let (file_name, codec) = IMAGES[0].clone();
+    let data = fs::read(file_name).unwrap();
+    let buffers = vec![data.clone(), data[..data.len() / 2].to_vec(), data];
+    let results = batch::from_memory_with_report(&buffers, codec, &config(), None);
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+}
@@ -0,0 +1,39 @@
+extern crate jpeg2000;
+
+use jpeg2000::boxes::Boxes;
+use jpeg2000::decode;
+use jpeg2000::error::{BoxError, DecodeError};
+
+/// A JP2 signature followed by a box whose extended length doesn't fit a seek offset.
+fn huge_xl_box() -> Vec<u8> {
+    let mut data = vec![
+        0x00, 0x00, 0x00, 0x0c, b'j', b'P', b' ', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+    ];
+    data.extend_from_slice(&1u32.to_be_bytes());
+    data.extend_from_slice(b"xml ");
+    data.extend_from_slice(&0xffff_ffff_ffff_fff0u64.to_be_bytes());
+    data.extend_from_slice(b"<x/>");
+    data
+}
+
+#[test]
+fn probe_rejects_huge_xl_box() {
+    match decode::probe_memory(&huge_xl_box()) {
+        Err(DecodeError::InvalidBox(BoxError::InvalidLength { offset, box_type })) => {
+            assert_eq!(offset, 12);
+            assert_eq!(&box_type, b"xml ");
+        }
+        other => panic!("expected an invalid box length, got {:?}", other),
+    }
+}
+
+#[test]
+fn boxes_reject_huge_xl_box() {
+    let data = huge_xl_box();
+    let mut boxes = Boxes::new(&data);
+    assert!(boxes.next().unwrap().is_ok());
+    match boxes.next() {
+        Some(Err(BoxError::InvalidLength { offset: 12, .. })) => {}
+        other => panic!("expected an invalid box length, got {:?}", other),
+    }
+}
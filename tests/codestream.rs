@@ -0,0 +1,58 @@
+extern crate jpeg2000;
+
+use jpeg2000::codestream;
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use std::io;
+
+/// A codestream of a single 8-bit pixel, whose one packet is empty.
+fn single_pixel(tile_part_len: u32) -> Vec<u8> {
+    let mut data = vec![0xff, 0x4f];
+    // SIZ: a 1x1 image in a single tile, with one unsigned 8-bit component.
+    data.extend_from_slice(&[0xff, 0x51, 0x00, 0x29, 0x00, 0x00]);
+    for value in &[1u32, 1, 0, 0, 1, 1, 0, 0] {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+    data.extend_from_slice(&[0x00, 0x01, 0x07, 0x01, 0x01]);
+    // COD: one layer, no decomposition levels, 64x64 code-blocks and the reversible wavelet.
+    data.extend_from_slice(&[
+        0xff, 0x52, 0x00, 0x0c, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x04, 0x04, 0x00, 0x01,
+    ]);
+    // QCD: no quantisation.
+    data.extend_from_slice(&[0xff, 0x5c, 0x00, 0x04, 0x40, 0x48]);
+    // SOT of tile 0, SOD and an empty packet.
+    data.extend_from_slice(&[0xff, 0x90, 0x00, 0x0a, 0x00, 0x00]);
+    data.extend_from_slice(&tile_part_len.to_be_bytes());
+    data.extend_from_slice(&[0x00, 0x01, 0xff, 0x93, 0x00, 0xff, 0xd9]);
+    data
+}
+
+#[test]
+fn scan_single_pixel() {
+    let info = codestream::scan_memory(&single_pixel(15)).unwrap();
+    assert!(!info.truncated);
+
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::GRAY),
+        ..DecodeConfig::default()
+    };
+    let image = decode::from_memory(&single_pixel(15), Codec::J2K, config, None).unwrap();
+    assert_eq!(image.pixel(0, 0)[0], 128);
+}
+
+#[test]
+fn tile_part_length_within_header() {
+    // A tile-part length of 12 ends at the SOD marker, so skipping the data would go backwards.
+    for tile_part_len in &[1, 12, 13] {
+        match codestream::scan_memory(&single_pixel(*tile_part_len)) {
+            Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {}
+            other => panic!("{}: expected invalid data, got {:?}", tile_part_len, other),
+        }
+    }
+
+    // Decoding reads the marker segments for the report too, this must not hang either.
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::GRAY),
+        ..DecodeConfig::default()
+    };
+    let _ = decode::from_memory(&single_pixel(12), Codec::J2K, config, None);
+}
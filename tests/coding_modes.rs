@@ -0,0 +1,138 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, CodingModes, EncodeConfig, Plane, Planes};
+use jpeg2000::error::EncodeError;
+
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 24;
+
+fn samples() -> Vec<u8> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| (((i % WIDTH) * 5) ^ ((i / WIDTH) * 9)) as u8)
+        .collect()
+}
+
+fn encode_with(samples: &[u8], codec: Codec, modes: CodingModes) -> Result<Vec<u8>, EncodeError> {
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::GRAY),
+        planes: vec![Plane::from(samples)],
+    };
+    let config = EncodeConfig {
+        coding_modes: modes,
+        ..EncodeConfig::default()
+    };
+    encode::to_memory(&planes, codec, config, None)
+}
+
+/// Each mode the linked OpenJPEG can encode on its own and all of them together.
+fn supported_modes() -> Vec<CodingModes> {
+    let none = CodingModes::default();
+    vec![
+        CodingModes {
+            sop_markers: true,
+            ..none
+        },
+        CodingModes {
+            eph_markers: true,
+            ..none
+        },
+        CodingModes {
+            reset: true,
+            ..none
+        },
+        CodingModes {
+            vertically_causal: true,
+            ..none
+        },
+        CodingModes {
+            predictable_termination: true,
+            ..none
+        },
+        CodingModes {
+            sop_markers: true,
+            eph_markers: true,
+            reset: true,
+            vertically_causal: true,
+            predictable_termination: true,
+            ..none
+        },
+    ]
+}
+
+#[test]
+fn coding_modes_are_read_back() {
+    let samples = samples();
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::GRAY),
+        ..DecodeConfig::default()
+    };
+
+    for codec in &[Codec::J2K, Codec::JP2] {
+        for modes in supported_modes() {
+            let encoded = encode_with(&samples, codec.clone(), modes).unwrap();
+            assert_eq!(decode::probe_memory(&encoded).unwrap().modes, modes);
+
+            let (decoded, report) =
+                decode::from_memory_with_report(&encoded, codec.clone(), config.clone(), None)
+                    .unwrap();
+            assert_eq!(report.modes, modes);
+            let pixels: Vec<u8> = decoded.as_raw().iter().step_by(4).cloned().collect();
+            assert!(
+                pixels == samples,
+                "{:?} {:?} changed the pixels",
+                codec,
+                modes
+            );
+        }
+    }
+}
+
+#[test]
+fn packet_markers_are_written() {
+    let samples = samples();
+    let count = |data: &[u8], marker: [u8; 2]| data.windows(2).filter(|w| *w == marker).count();
+
+    let plain = encode_with(&samples, Codec::J2K, CodingModes::default()).unwrap();
+    assert_eq!(count(&plain, [0xff, 0x91]), 0);
+    assert_eq!(count(&plain, [0xff, 0x92]), 0);
+
+    let modes = CodingModes {
+        sop_markers: true,
+        eph_markers: true,
+        ..CodingModes::default()
+    };
+    let marked = encode_with(&samples, Codec::J2K, modes).unwrap();
+    // One packet per resolution, and the height of 24 leaves room for four decomposition levels.
+    assert_eq!(count(&marked, [0xff, 0x91]), 5);
+    assert_eq!(count(&marked, [0xff, 0x92]), 5);
+}
+
+/// OpenJPEG 2.3 writes past the buffers of small code-blocks with these modes.
+#[test]
+fn overflowing_modes_are_rejected() {
+    let samples = samples();
+    let none = CodingModes::default();
+    let modes = [
+        CodingModes {
+            bypass: true,
+            ..none
+        },
+        CodingModes {
+            termall: true,
+            ..none
+        },
+        CodingModes {
+            segmentation_symbols: true,
+            ..none
+        },
+    ];
+    for modes in &modes {
+        match encode_with(&samples, Codec::J2K, *modes) {
+            Err(EncodeError::InvalidConfig(_)) => {}
+            other => panic!("{:?}: expected an invalid config, got {:?}", modes, other),
+        }
+    }
+}
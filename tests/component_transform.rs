@@ -0,0 +1,110 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, ComponentTransform, CustomTransform, EncodeConfig};
+use jpeg2000::encode::{Plane, Planes, QualityLayer, Transform};
+use jpeg2000::error::EncodeError;
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 32;
+
+fn channels() -> Vec<Vec<u8>> {
+    (0..3u32)
+        .map(|c| {
+            (0..WIDTH * HEIGHT)
+                .map(|i| ((i % WIDTH) * (c + 3) + (i / WIDTH) * 4 + c * 60) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+fn encode_with(
+    channels: &[Vec<u8>],
+    codec: Codec,
+    transform: CustomTransform,
+) -> Result<Vec<u8>, EncodeError> {
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    };
+    let config = EncodeConfig {
+        transform: Transform::Irreversible,
+        quality_layers: vec![QualityLayer::Psnr(55.0)],
+        component_transform: ComponentTransform::Custom(transform),
+        ..EncodeConfig::default()
+    };
+    encode::to_memory(&planes, codec, config, None)
+}
+
+fn decorrelation() -> CustomTransform {
+    CustomTransform {
+        matrix: vec![0.5, 0.5, 0.0, 0.5, -0.5, 0.0, 0.0, 0.25, 0.75],
+        dc_shifts: vec![128, 0, 64],
+    }
+}
+
+#[test]
+fn custom_transform_round_trip() {
+    let channels = channels();
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let encoded = encode_with(&channels, codec.clone(), decorrelation()).unwrap();
+        let decoded = decode::from_memory(&encoded, codec.clone(), config.clone(), None).unwrap();
+
+        // The transform and the wavelet are done in floating point and the layer is lossy, but
+        // with a high PSNR samples stay close.
+        for (i, pixel) in decoded.as_raw().chunks(4).enumerate() {
+            for c in 0..3 {
+                let diff = (i32::from(pixel[c]) - i32::from(channels[c][i])).abs();
+                assert!(diff <= 4, "{:?} pixel {} component {}", codec, i, c);
+            }
+        }
+    }
+}
+
+#[test]
+fn custom_transform_is_checked() {
+    let channels = channels();
+    let transforms = vec![
+        CustomTransform {
+            matrix: vec![1.0; 4],
+            dc_shifts: Vec::new(),
+        },
+        CustomTransform {
+            matrix: vec![1.0, 0.0, 0.0, 0.0, f32::NAN, 0.0, 0.0, 0.0, 1.0],
+            dc_shifts: Vec::new(),
+        },
+        CustomTransform {
+            dc_shifts: vec![128, 128],
+            ..decorrelation()
+        },
+    ];
+    for transform in transforms {
+        match encode_with(&channels, Codec::J2K, transform.clone()) {
+            Err(EncodeError::InvalidConfig(_)) => {}
+            other => panic!(
+                "{:?}: expected an invalid config, got {:?}",
+                transform, other
+            ),
+        }
+    }
+}
+
+/// OpenJPEG only finds out that a matrix can't be inverted during the setup, which then fails
+/// with the matrix still set.
+#[test]
+fn singular_transform_fails_setup() {
+    let transform = CustomTransform {
+        matrix: vec![1.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        dc_shifts: Vec::new(),
+    };
+    match encode_with(&channels(), Codec::J2K, transform) {
+        Err(EncodeError::SetupEncoder { .. }) => {}
+        other => panic!("expected the setup to fail, got {:?}", other),
+    }
+}
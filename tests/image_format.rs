@@ -0,0 +1,114 @@
+#![cfg(feature = "image")]
+
+extern crate image;
+extern crate jpeg2000;
+
+use image::{ColorType, ExtendedColorType, ImageDecoder, ImageEncoder};
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes, Samples};
+use jpeg2000::image_format::{Jpeg2000Decoder, Jpeg2000Encoder};
+use std::io::Cursor;
+
+const WIDTH: u32 = 24;
+const HEIGHT: u32 = 16;
+
+fn encode(buf: &[u8], codec: Codec, color_type: ExtendedColorType) -> Vec<u8> {
+    let mut output = Vec::new();
+    Jpeg2000Encoder::new(&mut output, codec)
+        .write_image(buf, WIDTH, HEIGHT, color_type)
+        .unwrap();
+    output
+}
+
+fn decode(data: Vec<u8>) -> (ColorType, Vec<u8>) {
+    let decoder = Jpeg2000Decoder::new(Cursor::new(data)).unwrap();
+    assert_eq!(decoder.dimensions(), (WIDTH, HEIGHT));
+    let color_type = decoder.color_type();
+    let mut buf = vec![0; decoder.total_bytes() as usize];
+    decoder.read_image(&mut buf).unwrap();
+    (color_type, buf)
+}
+
+fn to_bytes(samples: &[u16]) -> Vec<u8> {
+    samples.iter().flat_map(|s| s.to_ne_bytes()).collect()
+}
+
+#[test]
+fn sixteen_bit_round_trip() {
+    let samples: Vec<u16> = (0..WIDTH * HEIGHT * 4)
+        .map(|i| (i * 2731 % 65536) as u16)
+        .collect();
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let encoded = encode(
+            &to_bytes(&samples),
+            codec.clone(),
+            ExtendedColorType::Rgba16,
+        );
+        let (color_type, decoded) = decode(encoded);
+        assert_eq!(color_type, ColorType::Rgba16);
+        assert!(decoded == to_bytes(&samples), "{:?}", codec);
+    }
+}
+
+#[test]
+fn sixteen_bit_gray_is_expanded() {
+    let samples: Vec<u16> = (0..WIDTH * HEIGHT).map(|i| (i * 173) as u16).collect();
+    let encoded = encode(&to_bytes(&samples), Codec::JP2, ExtendedColorType::L16);
+    let (color_type, decoded) = decode(encoded);
+    assert_eq!(color_type, ColorType::Rgba16);
+    let expected: Vec<u16> = samples
+        .iter()
+        .flat_map(|s| vec![*s, *s, *s, u16::MAX])
+        .collect();
+    assert!(decoded == to_bytes(&expected));
+}
+
+#[test]
+fn eight_bit_round_trip() {
+    let samples: Vec<u8> = (0..WIDTH * HEIGHT * 3).map(|i| (i * 7) as u8).collect();
+    let encoded = encode(&samples, Codec::JP2, ExtendedColorType::Rgb8);
+    let (color_type, decoded) = decode(encoded);
+    assert_eq!(color_type, ColorType::Rgba8);
+    let expected: Vec<u8> = samples
+        .chunks(3)
+        .flat_map(|p| vec![p[0], p[1], p[2], 255])
+        .collect();
+    assert_eq!(decoded, expected);
+}
+
+/// 8-bit RGBA output keeps the most significant bits and moves signed samples up by half their
+/// range.
+#[test]
+fn samples_are_scaled_to_eight_bits() {
+    let wide: Vec<u16> = (0..WIDTH * HEIGHT)
+        .map(|i| (i * 11 % 4096) as u16)
+        .collect();
+    let signed: Vec<i16> = (0..WIDTH * HEIGHT)
+        .map(|i| (i as i32 * 5 % 256 - 128) as i16)
+        .collect();
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::GRAY),
+        ..DecodeConfig::default()
+    };
+
+    let planes = [
+        Plane::new(Samples::U16(&wide), 12, false),
+        Plane::new(Samples::I16(&signed), 8, true),
+    ];
+    let expected: [Vec<u8>; 2] = [
+        wide.iter().map(|s| (s >> 4) as u8).collect(),
+        signed.iter().map(|s| (s + 128) as u8).collect(),
+    ];
+    for (plane, expected) in planes.iter().zip(&expected) {
+        let image = Planes {
+            width: WIDTH,
+            height: HEIGHT,
+            color_space: Some(ColorSpace::GRAY),
+            planes: vec![*plane],
+        };
+        let encoded = encode::to_memory(&image, Codec::J2K, EncodeConfig::default(), None).unwrap();
+        let decoded = decode::from_memory(&encoded, Codec::J2K, config.clone(), None).unwrap();
+        let gray: Vec<u8> = decoded.as_raw().iter().step_by(4).cloned().collect();
+        assert_eq!(&gray, expected);
+    }
+}
@@ -0,0 +1,179 @@
+extern crate jpeg2000;
+
+use jpeg2000::boxes::{self, Boxes};
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, CodingModes, EncodeConfig, IndexMarkers, Plane, Planes, Tiles};
+
+const WIDTH: u32 = 150;
+const HEIGHT: u32 = 100;
+
+const TLM: u16 = 0xff55;
+const PLT: u16 = 0xff58;
+const SOT: u16 = 0xff90;
+const SOD: u16 = 0xff93;
+const EOC: u16 = 0xffd9;
+
+fn channels() -> Vec<Vec<u8>> {
+    (0..3u32)
+        .map(|c| {
+            (0..WIDTH * HEIGHT)
+                .map(|i| ((i % WIDTH) * (c + 2) + (i / WIDTH) * 7) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+fn encode_with(channels: &[Vec<u8>], codec: Codec, modes: CodingModes) -> Vec<u8> {
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    };
+    let config = EncodeConfig {
+        tiles: Some(Tiles {
+            width: 64,
+            height: 64,
+            origin: (0, 0),
+        }),
+        coding_modes: modes,
+        index_markers: IndexMarkers {
+            tile_part_lengths: true,
+            packet_lengths: true,
+        },
+        ..EncodeConfig::default()
+    };
+    encode::to_memory(&planes, codec, config, None).unwrap()
+}
+
+fn codestream(data: &[u8], codec: &Codec) -> Vec<u8> {
+    if *codec == Codec::J2K {
+        return data.to_vec();
+    }
+    let jp2c = Boxes::new(data).find(boxes::CODESTREAM).unwrap().unwrap();
+    jp2c.payload.to_vec()
+}
+
+fn read_u16(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+fn read_u32(data: &[u8]) -> u32 {
+    u32::from_be_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// A tile-part with the packet lengths of its PLT marker segments.
+struct TilePart {
+    tile: u16,
+    len: u32,
+    body_len: usize,
+    packet_lengths: Vec<usize>,
+}
+
+/// The TLM entries of the main header and the tile-parts of a codestream.
+fn index(codestream: &[u8]) -> (Vec<(u16, u32)>, Vec<TilePart>) {
+    let mut entries = Vec::new();
+    let mut position = 2;
+    while read_u16(&codestream[position..]) != SOT {
+        let len = read_u16(&codestream[position + 2..]) as usize;
+        if read_u16(&codestream[position..]) == TLM {
+            // Ztlm, then Stlm for 16-bit tile indices and 32-bit lengths.
+            assert_eq!(codestream[position + 5], 0x60);
+            for entry in codestream[position + 6..position + 2 + len].chunks(6) {
+                entries.push((read_u16(entry), read_u32(&entry[2..])));
+            }
+        }
+        position += 2 + len;
+    }
+
+    let mut parts = Vec::new();
+    while read_u16(&codestream[position..]) == SOT {
+        let start = position;
+        let len = read_u32(&codestream[start + 6..]);
+        let end = start + len as usize;
+        let mut packet_lengths = Vec::new();
+        position += 12;
+        while read_u16(&codestream[position..]) != SOD {
+            let segment_len = read_u16(&codestream[position + 2..]) as usize;
+            if read_u16(&codestream[position..]) == PLT {
+                // Zplt, then lengths of seven bits per byte with the high bit continuing them.
+                let mut packet_len = 0;
+                for byte in &codestream[position + 5..position + 2 + segment_len] {
+                    packet_len = (packet_len << 7) | usize::from(byte & 0x7f);
+                    if byte & 0x80 == 0 {
+                        packet_lengths.push(packet_len);
+                        packet_len = 0;
+                    }
+                }
+            }
+            position += 2 + segment_len;
+        }
+        parts.push(TilePart {
+            tile: read_u16(&codestream[start + 4..]),
+            len,
+            body_len: end - (position + 2),
+            packet_lengths,
+        });
+        position = end;
+    }
+    assert_eq!(read_u16(&codestream[position..]), EOC);
+    (entries, parts)
+}
+
+#[test]
+fn tile_part_lengths_are_listed() {
+    let channels = channels();
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let encoded = encode_with(&channels, codec.clone(), CodingModes::default());
+        let (entries, parts) = index(&codestream(&encoded, codec));
+
+        // Three columns and two rows of tiles, each in one tile-part.
+        assert_eq!(parts.len(), 6);
+        let expected: Vec<(u16, u32)> = parts.iter().map(|part| (part.tile, part.len)).collect();
+        assert_eq!(entries, expected, "{:?}", codec);
+    }
+}
+
+#[test]
+fn packet_lengths_add_up() {
+    let channels = channels();
+    for codec in &[Codec::J2K, Codec::JP2] {
+        for keep_sop in &[false, true] {
+            let modes = CodingModes {
+                sop_markers: *keep_sop,
+                ..CodingModes::default()
+            };
+            let encoded = encode_with(&channels, codec.clone(), modes);
+            let (_, parts) = index(&codestream(&encoded, codec));
+            for part in &parts {
+                assert!(!part.packet_lengths.is_empty());
+                let total: usize = part.packet_lengths.iter().sum();
+                assert_eq!(total, part.body_len, "{:?} tile {}", codec, part.tile);
+            }
+
+            // The SOP markers needed to find the packets are only kept if asked for.
+            let info = decode::probe_memory(&encoded).unwrap();
+            assert_eq!(info.modes.sop_markers, *keep_sop, "{:?}", codec);
+        }
+    }
+}
+
+#[test]
+fn indexed_round_trip() {
+    let channels = channels();
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let encoded = encode_with(&channels, codec.clone(), CodingModes::default());
+        let info = decode::probe_memory(&encoded).unwrap();
+        assert!(info.index_markers.tile_part_lengths && info.index_markers.packet_lengths);
+
+        let decoded = decode::from_memory(&encoded, codec.clone(), config.clone(), None).unwrap();
+        for (i, pixel) in decoded.as_raw().chunks(4).enumerate() {
+            let expected = [channels[0][i], channels[1][i], channels[2][i], 255];
+            assert_eq!(pixel, expected, "{:?} pixel {}", codec, i);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+extern crate jpeg2000;
+
+use jpeg2000::boxes::{self, BitDepth, BoxContent, Boxes, ColorBox, Jp2Box};
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, ColorSpecification, EncodeConfig, GridResolution, Jp2Boxes};
+use jpeg2000::encode::{Plane, Planes, Samples, UuidBox, XMP_UUID};
+
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 30;
+
+fn channels() -> Vec<Vec<u8>> {
+    (0..3u32)
+        .map(|c| {
+            (0..WIDTH * HEIGHT)
+                .map(|i| ((i % WIDTH) * (c + 4) + (i / WIDTH) * 5) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+fn encode_with(planes: &Planes, boxes: Jp2Boxes) -> Vec<u8> {
+    let config = EncodeConfig {
+        jp2_boxes: Some(boxes),
+        ..EncodeConfig::default()
+    };
+    encode::to_memory(planes, Codec::JP2, config, None).unwrap()
+}
+
+fn find<'a>(boxes: Boxes<'a>, box_type: boxes::BoxType) -> Jp2Box<'a> {
+    boxes.find(box_type).unwrap().unwrap()
+}
+
+fn header(data: &[u8]) -> Jp2Box<'_> {
+    find(Boxes::new(data), boxes::HEADER)
+}
+
+fn color(data: &[u8]) -> ColorBox<'_> {
+    match find(header(data).children(), boxes::COLOR).parse().unwrap() {
+        BoxContent::Color(color) => color,
+        other => panic!("expected a colour specification, got {:?}", other),
+    }
+}
+
+/// The stored resolutions keep five significant digits.
+fn assert_resolution(parsed: &GridResolution, expected: &GridResolution) {
+    let close = |a: f64, b: f64| ((a - b) / b).abs() < 1e-4;
+    assert!(
+        close(parsed.horizontal, expected.horizontal) && close(parsed.vertical, expected.vertical),
+        "{:?} instead of {:?}",
+        parsed,
+        expected
+    );
+}
+
+#[test]
+fn boxes_are_written() {
+    let channels = channels();
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    };
+    let capture = GridResolution::from_dpi(300.0, 300.0);
+    let display = GridResolution::from_dpi(72.0, 96.0);
+    let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec();
+    let encoded = encode_with(
+        &planes,
+        Jp2Boxes {
+            capture_resolution: Some(capture),
+            display_resolution: Some(display),
+            xml: vec!["<note>round trip</note>".to_string()],
+            uuids: vec![UuidBox {
+                uuid: XMP_UUID,
+                data: xmp.clone(),
+            }],
+            ..Jp2Boxes::default()
+        },
+    );
+
+    match find(header(&encoded).children(), boxes::IMAGE_HEADER)
+        .parse()
+        .unwrap()
+    {
+        BoxContent::ImageHeader(ihdr) => {
+            assert_eq!(
+                (ihdr.width, ihdr.height, ihdr.components),
+                (WIDTH, HEIGHT, 3)
+            );
+            assert_eq!(
+                ihdr.bit_depth,
+                Some(BitDepth {
+                    precision: 8,
+                    signed: false,
+                })
+            );
+        }
+        other => panic!("expected an image header, got {:?}", other),
+    }
+
+    // Without a colour specification of its own the colour space of the image is enumerated.
+    let colr = color(&encoded);
+    assert_eq!((colr.method, colr.enumerated), (1, Some(16)));
+
+    let res = find(header(&encoded).children(), boxes::RESOLUTION);
+    match find(res.children(), boxes::CAPTURE_RESOLUTION)
+        .parse()
+        .unwrap()
+    {
+        BoxContent::CaptureResolution(resolution) => assert_resolution(&resolution, &capture),
+        other => panic!("expected a capture resolution, got {:?}", other),
+    }
+    match find(res.children(), boxes::DISPLAY_RESOLUTION)
+        .parse()
+        .unwrap()
+    {
+        BoxContent::DisplayResolution(resolution) => assert_resolution(&resolution, &display),
+        other => panic!("expected a display resolution, got {:?}", other),
+    }
+
+    match find(Boxes::new(&encoded), boxes::XML).parse().unwrap() {
+        BoxContent::Xml(xml) => assert_eq!(xml, &b"<note>round trip</note>"[..]),
+        other => panic!("expected XML, got {:?}", other),
+    }
+    match find(Boxes::new(&encoded), boxes::UUID).parse().unwrap() {
+        BoxContent::Uuid { uuid, data } => {
+            assert_eq!(uuid, XMP_UUID);
+            assert_eq!(data, &xmp[..]);
+        }
+        other => panic!("expected a UUID box, got {:?}", other),
+    }
+
+    // The boxes don't change the pixels.
+    let config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+    let decoded = decode::from_memory(&encoded, Codec::JP2, config, None).unwrap();
+    for (i, pixel) in decoded.as_raw().chunks(4).enumerate() {
+        let expected = [channels[0][i], channels[1][i], channels[2][i], 255];
+        assert_eq!(pixel, expected, "pixel {}", i);
+    }
+}
+
+#[test]
+fn icc_profile_is_embedded() {
+    let channels = channels();
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    };
+    let profile: Vec<u8> = (0..128u8).collect();
+    let encoded = encode_with(
+        &planes,
+        Jp2Boxes {
+            color: Some(ColorSpecification::Icc(profile.clone())),
+            ..Jp2Boxes::default()
+        },
+    );
+
+    let colr = color(&encoded);
+    assert_eq!((colr.method, colr.enumerated), (2, None));
+    assert_eq!(colr.icc_profile, Some(&profile[..]));
+}
+
+#[test]
+fn mixed_bit_depths_are_listed() {
+    let gray: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| (i * 3) as u8).collect();
+    let alpha: Vec<u16> = (0..WIDTH * HEIGHT).map(|i| (i * 7 % 4096) as u16).collect();
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::GRAY),
+        planes: vec![
+            Plane::from(&gray[..]),
+            Plane::new(Samples::U16(&alpha), 12, false),
+        ],
+    };
+    let encoded = encode_with(&planes, Jp2Boxes::default());
+
+    match find(header(&encoded).children(), boxes::IMAGE_HEADER)
+        .parse()
+        .unwrap()
+    {
+        BoxContent::ImageHeader(ihdr) => assert_eq!(ihdr.bit_depth, None),
+        other => panic!("expected an image header, got {:?}", other),
+    }
+    match find(header(&encoded).children(), boxes::BITS_PER_COMPONENT)
+        .parse()
+        .unwrap()
+    {
+        BoxContent::BitsPerComponent(depths) => {
+            let depths: Vec<(u8, bool)> = depths.iter().map(|d| (d.precision, d.signed)).collect();
+            assert_eq!(depths, vec![(8, false), (12, false)]);
+        }
+        other => panic!("expected the bits per component, got {:?}", other),
+    }
+    assert_eq!(color(&encoded).enumerated, Some(17));
+}
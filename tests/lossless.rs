@@ -0,0 +1,80 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes, Samples, Transform};
+use jpeg2000::raw::{Decoder, Stream};
+
+const WIDTH: u32 = 37;
+const HEIGHT: u32 = 21;
+
+/// Samples with edges and noise, so the wavelet transform has something to lose.
+fn samples(seed: u32, max: u32) -> Vec<u32> {
+    (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            let noise = (i.wrapping_mul(2_654_435_761) ^ seed) >> 7;
+            if x < WIDTH / 3 {
+                noise % (max + 1)
+            } else {
+                (x * 13 + y * 7 + seed) % (max + 1)
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn reversible_rgb_round_trip() {
+    let channels: Vec<Vec<u8>> = (0..3)
+        .map(|c| samples(c, 255).into_iter().map(|s| s as u8).collect())
+        .collect();
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    };
+    let decode_config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let config = EncodeConfig {
+            transform: Transform::Reversible,
+            ..EncodeConfig::default()
+        };
+        let encoded = encode::to_memory(&planes, codec.clone(), config, None).unwrap();
+        let decoded =
+            decode::from_memory(&encoded, codec.clone(), decode_config.clone(), None).unwrap();
+
+        assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+        for (i, pixel) in decoded.as_raw().chunks(4).enumerate() {
+            let expected = [channels[0][i], channels[1][i], channels[2][i], 255];
+            assert_eq!(pixel, expected, "{:?} pixel {}", codec, i);
+        }
+    }
+}
+
+#[test]
+fn reversible_12_bit_round_trip() {
+    let original: Vec<u16> = samples(5, 4095).into_iter().map(|s| s as u16).collect();
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::GRAY),
+        planes: vec![Plane::new(Samples::U16(&original), 12, false)],
+    };
+    let encoded = encode::to_memory(&planes, Codec::J2K, EncodeConfig::default(), None).unwrap();
+
+    let mut stream = Stream::from_memory(&encoded).unwrap();
+    let mut decoder = Decoder::new(Codec::J2K, None).unwrap();
+    decoder.setup(&DecodeConfig::default()).unwrap();
+    let mut image = decoder.read_header(&mut stream).unwrap();
+    decoder.decode(&mut stream, &mut image).unwrap();
+    decoder.end_decompress(&mut stream).unwrap();
+
+    assert_eq!(image.components().len(), 1);
+    assert_eq!(image.components()[0].prec, 12);
+    let expected: Vec<i32> = original.iter().map(|s| i32::from(*s)).collect();
+    assert_eq!(image.component_data(0), &expected[..]);
+}
@@ -0,0 +1,119 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes};
+
+const WIDTH: u32 = 16;
+const HEIGHT: u32 = 16;
+
+fn config() -> DecodeConfig {
+    DecodeConfig {
+        default_colorspace: Some(ColorSpace::GRAY),
+        ..DecodeConfig::default()
+    }
+}
+
+/// A lossless J2K codestream of a single 8-bit component.
+fn codestream(samples: &[u8]) -> Vec<u8> {
+    let planes = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: None,
+        planes: vec![Plane::from(samples)],
+    };
+    encode::to_memory(&planes, Codec::J2K, EncodeConfig::default(), None).unwrap()
+}
+
+fn gradient() -> Vec<u8> {
+    (0..WIDTH * HEIGHT).map(|i| (i * 3 % 256) as u8).collect()
+}
+
+#[test]
+fn truncated_codestream() {
+    let mut data = codestream(&gradient());
+    let len = data.len();
+    data.truncate(len - 20);
+
+    // OpenJPEG 2.3 fails on packet data which ends early.
+    match decode::from_memory(&data, Codec::J2K, config(), None) {
+        Err(err) => assert!(err.is_truncated(), "{}", err),
+        Ok(_) => panic!("decoded a truncated codestream"),
+    }
+}
+
+#[test]
+fn unknown_marker() {
+    let mut data = codestream(&gradient());
+    // An unknown marker segment right after the SIZ marker segment, before the COD marker.
+    let siz_len = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let position = 4 + siz_len;
+    let unknown = [0xff, 0x77, 0x00, 0x04, 0x12, 0x34];
+    data.splice(position..position, unknown.iter().cloned());
+
+    // OpenJPEG skips the segment and warns about it.
+    let (image, report) =
+        decode::from_memory_with_report(&data, Codec::J2K, config(), None).unwrap();
+    assert_eq!(image.pixel(1, 0)[0], 3);
+    assert!(!report.warnings.is_empty());
+}
+
+const PALETTE: [[u8; 3]; 4] = [[0, 0, 0], [255, 0, 0], [0, 255, 0], [10, 20, 30]];
+
+fn jp2_box(output: &mut Vec<u8>, kind: &[u8; 4], payload: &[u8]) {
+    output.extend_from_slice(&(payload.len() as u32 + 8).to_be_bytes());
+    output.extend_from_slice(kind);
+    output.extend_from_slice(payload);
+}
+
+/// A JP2 file whose single component holds indices into an RGB palette.
+fn palettised_jp2(indices: &[u8]) -> Vec<u8> {
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&HEIGHT.to_be_bytes());
+    ihdr.extend_from_slice(&WIDTH.to_be_bytes());
+    ihdr.extend_from_slice(&1u16.to_be_bytes());
+    ihdr.extend_from_slice(&[7, 7, 0, 0]);
+
+    let mut pclr = (PALETTE.len() as u16).to_be_bytes().to_vec();
+    pclr.extend_from_slice(&[3, 7, 7, 7]);
+    for entry in &PALETTE {
+        pclr.extend_from_slice(entry);
+    }
+
+    // Each channel maps component 0 through one palette column.
+    let cmap = [0, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 2];
+
+    let mut jp2h = Vec::new();
+    jp2_box(&mut jp2h, b"ihdr", &ihdr);
+    jp2_box(&mut jp2h, b"colr", &[1, 0, 0, 0, 0, 0, 16]);
+    jp2_box(&mut jp2h, b"pclr", &pclr);
+    jp2_box(&mut jp2h, b"cmap", &cmap);
+
+    let mut file = vec![
+        0x00, 0x00, 0x00, 0x0c, b'j', b'P', b' ', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+    ];
+    jp2_box(&mut file, b"ftyp", b"jp2 \0\0\0\0jp2 ");
+    jp2_box(&mut file, b"jp2h", &jp2h);
+    jp2_box(&mut file, b"jp2c", &codestream(indices));
+    file
+}
+
+#[test]
+fn palettised_jp2_file() {
+    let indices: Vec<u8> = (0..WIDTH * HEIGHT).map(|i| (i % 4) as u8).collect();
+    let data = palettised_jp2(&indices);
+
+    let image = decode::from_memory(&data, Codec::JP2, config(), None).unwrap();
+    for (i, index) in indices.iter().enumerate() {
+        let pixel = image.pixel(i as u32 % WIDTH, i as u32 / WIDTH);
+        assert_eq!(pixel[..3], PALETTE[*index as usize]);
+    }
+
+    let indexed = decode::indexed_from_memory(&data, Codec::JP2, config(), None).unwrap();
+    let expected: Vec<u16> = indices.iter().map(|index| u16::from(*index)).collect();
+    assert_eq!(indexed.indices, expected);
+    let entries: Vec<Vec<i32>> = PALETTE
+        .iter()
+        .map(|entry| entry.iter().map(|value| i32::from(*value)).collect())
+        .collect();
+    assert_eq!(indexed.palette.entries, entries);
+}
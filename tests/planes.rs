@@ -0,0 +1,88 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{Codec, DecodeConfig};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes, Samples};
+use jpeg2000::raw::{Decoder, Stream};
+
+const WIDTH: u32 = 29;
+const HEIGHT: u32 = 19;
+
+fn band(seed: i32, len: u32, min: i32, max: i32) -> Vec<i32> {
+    (0..len as i32)
+        .map(|i| min + (i * 37 + seed * 11 + (i >> 3) * seed) % (max - min + 1))
+        .collect()
+}
+
+/// Ten bands of different sample types, precisions and subsampling, more than a colour image
+/// can have.
+#[test]
+fn multispectral_round_trip() {
+    let full = WIDTH * HEIGHT;
+    // Subsampled planes cover every second sample, rounding up.
+    let half = WIDTH.div_ceil(2) * HEIGHT.div_ceil(2);
+
+    let bytes: Vec<Vec<u8>> = (0..6)
+        .map(|seed| band(seed, full, 0, 255).iter().map(|s| *s as u8).collect())
+        .collect();
+    let wide: Vec<u16> = band(6, full, 0, 4095).iter().map(|s| *s as u16).collect();
+    let signed: Vec<i16> = band(7, full, -32768, 32767)
+        .iter()
+        .map(|s| *s as i16)
+        .collect();
+    let deep = band(8, full, -(1 << 19), (1 << 19) - 1);
+    let subsampled: Vec<u16> = band(9, half, 0, 1023).iter().map(|s| *s as u16).collect();
+
+    let mut planes: Vec<Plane> = bytes.iter().map(|band| Plane::from(&band[..])).collect();
+    planes.push(Plane::new(Samples::U16(&wide), 12, false));
+    planes.push(Plane::from(&signed[..]));
+    planes.push(Plane::new(Samples::I32(&deep), 20, true));
+    planes.push(Plane {
+        subsampling: (2, 2),
+        ..Plane::new(Samples::U16(&subsampled), 10, false)
+    });
+    let image = Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: None,
+        planes,
+    };
+    let encoded = encode::to_memory(&image, Codec::J2K, EncodeConfig::default(), None).unwrap();
+
+    let mut stream = Stream::from_memory(&encoded).unwrap();
+    let mut decoder = Decoder::new(Codec::J2K, None).unwrap();
+    decoder.setup(&DecodeConfig::default()).unwrap();
+    let mut decoded = decoder.read_header(&mut stream).unwrap();
+    decoder.decode(&mut stream, &mut decoded).unwrap();
+    decoder.end_decompress(&mut stream).unwrap();
+
+    assert_eq!(decoded.components().len(), image.planes.len());
+    for (index, plane) in image.planes.iter().enumerate() {
+        let comp = &decoded.components()[index];
+        assert_eq!(comp.prec, u32::from(plane.precision), "band {}", index);
+        assert_eq!(comp.sgnd != 0, plane.signed, "band {}", index);
+        assert_eq!((comp.dx, comp.dy), plane.subsampling, "band {}", index);
+        assert_eq!(
+            comp.w as usize * comp.h as usize,
+            plane.len(),
+            "band {}",
+            index
+        );
+
+        let expected: Vec<i32> = match plane.samples {
+            Samples::U8(samples) => samples.iter().map(|s| i32::from(*s)).collect(),
+            Samples::U16(samples) => samples.iter().map(|s| i32::from(*s)).collect(),
+            Samples::I16(samples) => samples.iter().map(|s| i32::from(*s)).collect(),
+            Samples::I32(samples) => samples.to_vec(),
+        };
+        assert_eq!(
+            decoded.component_data(index),
+            &expected[..],
+            "band {}",
+            index
+        );
+    }
+    assert_eq!(
+        (decoded.components()[9].w, decoded.components()[9].h),
+        (15, 10)
+    );
+}
@@ -0,0 +1,31 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{Codec, ColorSpace};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes, RegionOfInterest};
+use jpeg2000::error::EncodeError;
+
+/// OpenJPEG 2.3 doesn't shift the coefficients of the region, so decoders would damage it.
+#[test]
+fn region_of_interest_is_rejected() {
+    let samples: Vec<u8> = (0..64u32 * 64).map(|i| (i % 256) as u8).collect();
+    let planes = Planes {
+        width: 64,
+        height: 64,
+        color_space: Some(ColorSpace::SRGB),
+        planes: vec![Plane::from(&samples[..]); 3],
+    };
+    let config = EncodeConfig {
+        region_of_interest: Some(RegionOfInterest {
+            component: 0,
+            shift: 16,
+        }),
+        ..EncodeConfig::default()
+    };
+
+    for codec in &[Codec::J2K, Codec::JP2] {
+        match encode::to_memory(&planes, codec.clone(), config.clone(), None) {
+            Err(EncodeError::InvalidConfig(_)) => {}
+            other => panic!("expected an invalid config, got {:?}", other.map(|_| ())),
+        }
+    }
+}
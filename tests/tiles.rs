@@ -0,0 +1,119 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{self, Codec, ColorSpace, DecodeConfig};
+use jpeg2000::encode::{Component, EncodeConfig, Plane, TileEncoder, TileRect, TiledImage, Tiles};
+use jpeg2000::error::EncodeError;
+use std::io::Cursor;
+
+const WIDTH: u32 = 150;
+const HEIGHT: u32 = 100;
+
+fn channels() -> Vec<Vec<u8>> {
+    (0..3u32)
+        .map(|c| {
+            (0..WIDTH * HEIGHT)
+                .map(|i| ((i % WIDTH) * (c + 1) + (i / WIDTH) * 3) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+fn image() -> TiledImage {
+    TiledImage {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        components: vec![
+            Component {
+                precision: 8,
+                signed: false,
+                subsampling: (1, 1),
+            };
+            3
+        ],
+    }
+}
+
+fn config() -> EncodeConfig {
+    EncodeConfig {
+        tiles: Some(Tiles {
+            width: 64,
+            height: 64,
+            origin: (0, 0),
+        }),
+        ..EncodeConfig::default()
+    }
+}
+
+/// The samples of a channel within a tile.
+fn cut(channel: &[u8], tile: &TileRect) -> Vec<u8> {
+    (tile.y0..tile.y1)
+        .flat_map(|y| {
+            let row = (y * WIDTH) as usize;
+            channel[row + tile.x0 as usize..row + tile.x1 as usize].to_vec()
+        })
+        .collect()
+}
+
+#[test]
+fn tiled_round_trip() {
+    let channels = channels();
+    let decode_config = DecodeConfig {
+        default_colorspace: Some(ColorSpace::SRGB),
+        ..DecodeConfig::default()
+    };
+
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let mut output = Cursor::new(Vec::new());
+        let mut tiles = Vec::new();
+        {
+            let image = image();
+            let mut encoder =
+                TileEncoder::new(&image, &mut output, codec.clone(), config(), None).unwrap();
+            while let Some(tile) = encoder.next_tile() {
+                let tile_planes: Vec<Vec<u8>> =
+                    channels.iter().map(|channel| cut(channel, &tile)).collect();
+                let tile_planes: Vec<Plane> = tile_planes
+                    .iter()
+                    .map(|plane| Plane::from(&plane[..]))
+                    .collect();
+                encoder.write_tile(&tile_planes).unwrap();
+                tiles.push(tile);
+            }
+            encoder.finish().unwrap();
+        }
+
+        // Three columns and two rows, the last ones cut to the image.
+        assert_eq!(tiles.len(), 6);
+        assert_eq!((tiles[2].x0, tiles[2].x1), (128, 150));
+        assert_eq!((tiles[5].y0, tiles[5].y1), (64, 100));
+
+        let encoded = output.into_inner();
+        let decoded =
+            decode::from_memory(&encoded, codec.clone(), decode_config.clone(), None).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (WIDTH, HEIGHT));
+        for (i, pixel) in decoded.as_raw().chunks(4).enumerate() {
+            let expected = [channels[0][i], channels[1][i], channels[2][i], 255];
+            assert_eq!(pixel, expected, "{:?} pixel {}", codec, i);
+        }
+    }
+}
+
+#[test]
+fn tiles_are_checked() {
+    let image = image();
+    let mut output = Cursor::new(Vec::new());
+    let mut encoder = TileEncoder::new(&image, &mut output, Codec::J2K, config(), None).unwrap();
+
+    // The first tile is 64x64, so a plane of the whole image doesn't fit it.
+    let channels = channels();
+    let planes: Vec<Plane> = channels.iter().map(|c| Plane::from(&c[..])).collect();
+    match encoder.write_tile(&planes) {
+        Err(EncodeError::InvalidInput(_)) => {}
+        other => panic!("expected invalid input, got {:?}", other),
+    }
+    match encoder.finish() {
+        Err(EncodeError::InvalidInput(_)) => {}
+        other => panic!("expected invalid input, got {:?}", other),
+    }
+}
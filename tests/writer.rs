@@ -0,0 +1,100 @@
+extern crate jpeg2000;
+
+use jpeg2000::decode::{Codec, ColorSpace};
+use jpeg2000::encode::{self, EncodeConfig, Plane, Planes};
+use jpeg2000::error::EncodeError;
+use std::io::{self, Cursor, Seek, SeekFrom, Write};
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 40;
+
+fn channels() -> Vec<Vec<u8>> {
+    (0..3u32)
+        .map(|c| {
+            (0..WIDTH * HEIGHT)
+                .map(|i| ((i * (c + 3)) ^ (i >> 4)) as u8)
+                .collect()
+        })
+        .collect()
+}
+
+fn planes<'a>(channels: &'a [Vec<u8>]) -> Planes<'a> {
+    Planes {
+        width: WIDTH,
+        height: HEIGHT,
+        color_space: Some(ColorSpace::SRGB),
+        planes: channels.iter().map(|c| Plane::from(&c[..])).collect(),
+    }
+}
+
+#[test]
+fn writer_output_matches_memory() {
+    let channels = channels();
+    let planes = planes(&channels);
+    for codec in &[Codec::J2K, Codec::JP2] {
+        let encoded =
+            encode::to_memory(&planes, codec.clone(), EncodeConfig::default(), None).unwrap();
+
+        // The output starts at the position of the writer.
+        let mut writer = Cursor::new(b"prefix".to_vec());
+        writer.seek(SeekFrom::End(0)).unwrap();
+        encode::to_writer(
+            &planes,
+            &mut writer,
+            codec.clone(),
+            EncodeConfig::default(),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(writer.position() as usize, 6 + encoded.len(), "{:?}", codec);
+        let written = writer.into_inner();
+        assert_eq!(&written[..6], b"prefix");
+        assert!(written[6..] == encoded[..], "{:?}", codec);
+    }
+}
+
+/// A writer failing once `limit` bytes were written.
+struct FailingWriter {
+    inner: Cursor<Vec<u8>>,
+    limit: u64,
+}
+
+impl Write for FailingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.inner.position() + buf.len() as u64 > self.limit {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for FailingWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn writer_error_is_returned() {
+    let channels = channels();
+    let planes = planes(&channels);
+    let mut writer = FailingWriter {
+        inner: Cursor::new(Vec::new()),
+        limit: 100,
+    };
+    match encode::to_writer(
+        &planes,
+        &mut writer,
+        Codec::J2K,
+        EncodeConfig::default(),
+        None,
+    ) {
+        Err(EncodeError::Io(ref err)) if err.kind() == io::ErrorKind::WriteZero => {}
+        other => panic!("expected the error of the writer, got {:?}", other),
+    }
+}